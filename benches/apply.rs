@@ -0,0 +1,146 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use insertion_set::{
+    HybridSortStrategy, InsertionSet, InsertionSortStrategy, SortStrategy, StdStableSortStrategy,
+};
+
+const TARGETS: usize = 8;
+
+fn padding_indices() -> Vec<usize> {
+    (0..1000).step_by(4).collect()
+}
+
+fn build_padding() -> InsertionSet<String> {
+    let mut insertions = InsertionSet::new();
+    insertions.insert_many_same(&padding_indices(), "nop".repeat(16));
+    insertions
+}
+
+fn apply_to_many(c: &mut Criterion) {
+    let originals = (0..1000).map(|i| i.to_string()).collect::<Vec<String>>();
+    let mut group = c.benchmark_group("apply_to_many");
+    group.bench_function("naive", |b| {
+        let indices = padding_indices();
+        let fill = "nop".repeat(16);
+        b.iter_batched(
+            || vec![originals.clone(); TARGETS],
+            |mut targets| {
+                // Queue a separate clone for every insertion into every target
+                for target in &mut targets {
+                    let mut insertions = InsertionSet::new();
+                    for &index in &indices {
+                        insertions.insert(index, fill.clone());
+                    }
+                    insertions.apply(target);
+                }
+                black_box(targets)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("scratch", |b| {
+        b.iter_batched(
+            || (build_padding(), vec![originals.clone(); TARGETS]),
+            |(insertions, mut targets)| {
+                let mut scratch = Vec::new();
+                for target in &mut targets {
+                    insertions.apply_with_scratch(target, &mut scratch);
+                }
+                insertions.discard();
+                black_box(targets)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("shared", |b| {
+        b.iter_batched(
+            || (build_padding(), vec![originals.clone(); TARGETS]),
+            |(insertions, mut targets)| {
+                insertions.apply_to_many(&mut targets);
+                black_box(targets)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn single_insertion(c: &mut Criterion) {
+    let mut target = (0..64).collect::<Vec<u32>>();
+    c.bench_function("single_insertion", |b| {
+        b.iter(|| {
+            for i in 0..1000u32 {
+                let mut insertions = InsertionSet::new();
+                insertions.insert(black_box(i as usize % 64), i);
+                insertions.apply(&mut target);
+                target.pop();
+            }
+        })
+    });
+}
+
+fn sort_million(c: &mut Criterion) {
+    // A multiplicative hash scatters the indices, so the set is far from sorted
+    let insertions = (0..1_000_000usize)
+        .map(|i| (i.wrapping_mul(2_654_435_761) % 1_000_000, i))
+        .collect::<InsertionSet<usize>>();
+    let mut group = c.benchmark_group("sort_million");
+    group.sample_size(10);
+    group.bench_function("serial", |b| {
+        b.iter_batched(
+            || insertions.clone(),
+            |mut insertions| {
+                insertions.sort_with(StdStableSortStrategy);
+                insertions
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    #[cfg(feature = "rayon")]
+    group.bench_function("parallel", |b| {
+        b.iter_batched(
+            || insertions.clone(),
+            |mut insertions| {
+                insertions.par_sort(insertion_set::PARALLEL_SORT_THRESHOLD);
+                insertions
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn sort_strategy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sort_strategy");
+    for len in [16, 64, 256, 4096] {
+        let sorted = (0..len).map(|i| (i, i)).collect::<Vec<(usize, usize)>>();
+        let shuffled = (0..len)
+            .map(|i| (i.wrapping_mul(2_654_435_761) % len, i))
+            .collect::<Vec<(usize, usize)>>();
+        for (order, input) in [("sorted", &sorted), ("shuffled", &shuffled)] {
+            group.bench_function(format!("hybrid/{}/{}", order, len), |b| {
+                b.iter_batched_ref(
+                    || input.clone(),
+                    |elements| HybridSortStrategy::default().sort_by_key(elements, |e| e.0),
+                    BatchSize::SmallInput,
+                )
+            });
+            group.bench_function(format!("insertion/{}/{}", order, len), |b| {
+                b.iter_batched_ref(
+                    || input.clone(),
+                    |elements| InsertionSortStrategy.sort_by_key(elements, |e| e.0),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    apply_to_many,
+    single_insertion,
+    sort_million,
+    sort_strategy
+);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Insertion sets that validate each index as it's queued.
+use alloc::vec::Vec;
+
+use crate::{Insertion, InsertionError, InsertionSet};
+
+/// A set of pending insertions against a target with a known length,
+/// which rejects out-of-bounds indices as soon as they're queued.
+///
+/// This catches a bad index at the call site that produced it,
+/// instead of deferring the panic (or error) to apply time.
+/// The plain [`InsertionSet`] skips this per-insertion check.
+#[derive(Clone, Debug)]
+pub struct CheckedInsertionSet<T> {
+    insertions: InsertionSet<T>,
+    /// The length of the target the insertions will be applied to
+    target_len: usize,
+}
+impl<T> CheckedInsertionSet<T> {
+    /// Create a new set for a target with the specified length
+    #[inline]
+    pub fn new(target_len: usize) -> Self {
+        CheckedInsertionSet {
+            insertions: InsertionSet::new(),
+            target_len,
+        }
+    }
+    /// The length of the target these insertions are checked against
+    #[inline]
+    pub fn target_len(&self) -> usize {
+        self.target_len
+    }
+    /// Queue the specified insertion, unless its index is out of bounds
+    ///
+    /// If there are multiple insertions at the same index,
+    /// they will be applied in the order queued.
+    #[inline]
+    pub fn try_push(&mut self, insertion: Insertion<T>) -> Result<(), InsertionError> {
+        if insertion.index > self.target_len {
+            return Err(InsertionError {
+                index: insertion.index,
+                len: self.target_len,
+            });
+        }
+        self.insertions.push(insertion);
+        Ok(())
+    }
+    /// Queue the element to be inserted before the given index, unless it's out of bounds
+    #[inline]
+    pub fn try_insert(&mut self, index: usize, element: T) -> Result<(), InsertionError> {
+        self.try_push(Insertion::new(index, element))
+    }
+    /// The insertions that have been queued so far
+    #[inline]
+    pub fn as_set(&self) -> &InsertionSet<T> {
+        &self.insertions
+    }
+    /// Unwrap the (already validated) insertions
+    #[inline]
+    pub fn into_inner(self) -> InsertionSet<T> {
+        self.insertions
+    }
+    /// Applies all the insertions to the specified target vector.
+    ///
+    /// Afterwards, the set is empty and checks future insertions against the new length of the target,
+    /// so it can be reused to queue more insertions for the same vector.
+    ///
+    /// Panics if the target doesn't have the expected length.
+    pub fn apply(&mut self, target: &mut Vec<T>) {
+        assert_eq!(
+            target.len(),
+            self.target_len,
+            "Target doesn't match the expected length"
+        );
+        self.insertions.apply(target);
+        self.target_len = target.len();
+    }
+    /// Apply all of the pending insertions against the specified vector,
+    /// returning the result
+    #[inline]
+    pub fn applied(mut self, mut target: Vec<T>) -> Vec<T> {
+        self.apply(&mut target);
+        target
+    }
+}
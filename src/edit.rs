@@ -0,0 +1,128 @@
+//! Batched edits, combining deletions with insertions.
+//!
+//! Calling [`Vec::remove`] in a loop has the same quadratic blowup as [`Vec::insert`],
+//! so an [`EditSet`] queues both kinds of edits and applies them together.
+use alloc::vec::Vec;
+
+use crate::{BulkDeleter, Insertion, InsertionSet};
+
+/// A set of pending deletions and insertions on a Vec
+///
+/// All indices refer to the original vector (before any edits are applied).
+/// Deletions are always applied before insertions,
+/// so an insertion at the index of a deleted element takes its place
+/// (before the next original element).
+pub struct EditSet<T> {
+    insertions: InsertionSet<T>,
+    /// The original indices to delete, in the order queued
+    deletions: Vec<usize>,
+}
+impl<T> EditSet<T> {
+    /// Create a new EditSet
+    #[inline]
+    pub fn new() -> Self {
+        EditSet {
+            insertions: InsertionSet::new(),
+            deletions: Vec::new(),
+        }
+    }
+    /// Queue the specified insertion
+    ///
+    /// If there are multiple insertions at the same index,
+    /// they will be applied in the order queued.
+    #[inline]
+    pub fn push(&mut self, insertion: Insertion<T>) {
+        self.insertions.push(insertion)
+    }
+    /// Insert the element to be inserted before the given (original) index
+    #[inline]
+    pub fn insert(&mut self, index: usize, element: T) {
+        self.insertions.insert(index, element)
+    }
+    /// Queue the element at the given (original) index for deletion
+    ///
+    /// Deleting the same index multiple times has no additional effect.
+    #[inline]
+    pub fn delete(&mut self, index: usize) {
+        self.deletions.push(index)
+    }
+    /// The number of queued insertions
+    #[inline]
+    pub fn desired_insertions(&self) -> usize {
+        self.insertions.desired_insertions()
+    }
+    /// The number of queued deletions (including any duplicates)
+    #[inline]
+    pub fn desired_deletions(&self) -> usize {
+        self.deletions.len()
+    }
+    /// Applies all the edits to the specified target vector.
+    ///
+    /// The deletions are performed first, in a single left-shifting pass,
+    /// and then the insertions are performed in a single right-shifting pass.
+    /// Both passes reuse the existing memory of the vector,
+    /// so the runtime is still `O(n + m)` on average.
+    ///
+    /// Panics if any deletion or insertion index is out of bounds of the original vector.
+    /// Every index is checked before anything is deleted,
+    /// so on panic the target is left completely untouched.
+    pub fn apply(&mut self, target: &mut Vec<T>) {
+        self.deletions.sort_unstable();
+        self.deletions.dedup();
+        if let Some(&last) = self.deletions.last() {
+            assert!(
+                last < target.len(),
+                "Invalid deletion index {} >= len {}",
+                last,
+                target.len()
+            );
+        }
+        // Anchored insertions are resolved against the target after the deletions
+        let remaining = target.len() - self.deletions.len();
+        if let Err(error) = self.insertions.validate_split(target.len(), remaining) {
+            panic!("{}", error);
+        }
+        let mut deleter = BulkDeleter::new(target);
+        for &index in &self.deletions {
+            deleter.delete_range(index..index + 1);
+        }
+        deleter.finish();
+        /*
+         * The insertions are translated in sorted order,
+         * so we can count the preceding deletions with a single walk.
+         */
+        let mut deleted_before = 0;
+        let deletions = &self.deletions;
+        self.insertions.apply_via_index_map(target, |index| {
+            while deletions
+                .get(deleted_before)
+                .is_some_and(|&deleted| deleted < index)
+            {
+                deleted_before += 1;
+            }
+            index - deleted_before
+        });
+        self.deletions.clear();
+    }
+    /// Applies all the edits to the specified vector, returning the result
+    #[inline]
+    pub fn applied(mut self, mut target: Vec<T>) -> Vec<T> {
+        self.apply(&mut target);
+        target
+    }
+}
+impl<T> From<InsertionSet<T>> for EditSet<T> {
+    #[inline]
+    fn from(insertions: InsertionSet<T>) -> Self {
+        EditSet {
+            insertions,
+            deletions: Vec::new(),
+        }
+    }
+}
+impl<T> Default for EditSet<T> {
+    #[inline]
+    fn default() -> Self {
+        EditSet::new()
+    }
+}
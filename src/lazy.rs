@@ -0,0 +1,89 @@
+//! Insertion sets whose elements are only constructed once they're applied.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use crate::{apply_exact_insertions, Insertion, InsertionSet};
+
+/// A set of pending insertions whose elements are computed lazily.
+///
+/// Each element is queued as a closure, which is only invoked when the set is applied.
+/// If the set is dropped without being applied, none of the closures are ever called.
+/// Since that's a legitimate way to skip the work entirely,
+/// the `drop_check` feature never complains about dropping a lazy set.
+/// Otherwise, this has exactly the same ordering guarantees as an [`InsertionSet`].
+///
+/// By default the closures are boxed, so each one can have a different type.
+pub struct LazyInsertionSet<T, F = Box<dyn FnOnce() -> T>> {
+    thunks: InsertionSet<F>,
+    marker: PhantomData<fn() -> T>,
+}
+impl<T, F: FnOnce() -> T> LazyInsertionSet<T, F> {
+    /// Create a new LazyInsertionSet
+    #[inline]
+    pub fn new() -> Self {
+        LazyInsertionSet {
+            thunks: InsertionSet::new(),
+            marker: PhantomData,
+        }
+    }
+    /// Queue an element to be computed and inserted before the given index
+    ///
+    /// If multiple elements are queued to be inserted at the same index,
+    /// they will be applied in the original order queued.
+    #[inline]
+    pub fn insert_with(&mut self, index: usize, func: F) {
+        self.thunks.insert(index, func)
+    }
+    /// The number of insertions that are currently queued
+    #[inline]
+    pub fn desired_insertions(&self) -> usize {
+        self.thunks.desired_insertions()
+    }
+    /// Computes all the elements, and applies them to the specified target vector.
+    ///
+    /// The closures are invoked in reverse order of their (sorted) position,
+    /// since each element is computed right as it's placed.
+    ///
+    /// Panics if any of the insertion indices are out of bounds (before invoking any closures).
+    pub fn apply(&mut self, target: &mut Vec<T>) {
+        if let Err(error) = self.thunks.validate(target.len()) {
+            panic!("{}", error);
+        }
+        apply_exact_insertions(
+            target,
+            self.thunks
+                .drain_sorted()
+                .map(|Insertion { index, element }| Insertion::new(index, element())),
+        );
+    }
+    /// Apply all of the pending insertions against the specified vector,
+    /// returning the result
+    #[inline]
+    pub fn applied(mut self, mut target: Vec<T>) -> Vec<T> {
+        self.apply(&mut target);
+        target
+    }
+}
+impl<T> LazyInsertionSet<T> {
+    /// Box the closure, then queue it to be computed and inserted before the given index
+    ///
+    /// This allows queueing closures of different types in the same set.
+    #[inline]
+    pub fn insert_boxed<G: FnOnce() -> T + 'static>(&mut self, index: usize, func: G) {
+        self.insert_with(index, Box::new(func))
+    }
+}
+/// Discard the closures before the inner set is dropped, so it never trips the `drop_check` feature
+#[cfg(all(feature = "drop_check", debug_assertions, not(test)))]
+impl<T, F> Drop for LazyInsertionSet<T, F> {
+    fn drop(&mut self) {
+        self.thunks.clear();
+    }
+}
+impl<T, F: FnOnce() -> T> Default for LazyInsertionSet<T, F> {
+    #[inline]
+    fn default() -> Self {
+        LazyInsertionSet::new()
+    }
+}
@@ -10,19 +10,80 @@
 //! This code was originally copied from the first prototype compiler for [DuckLogic].
 //! It was inspired by the way the [B3 JIT] handles insertions.
 //!
+//! ## Features
+//! The crate supports `no_std` (with an allocator) by disabling the default `std` feature.
+//! Only a few helpers that need the standard library (like a `HashMap`) require it.
+//!
+//! The `rayon` feature adds [`InsertionSet::par_sort`] for sorting very large sets in parallel.
+//!
+//! The nightly-only `allocator_api` feature makes [`InsertionSet`] generic over its allocator
+//! (like `Vec<T, A>`), and lets it be applied to vectors with a custom allocator.
+//!
+//! The `serde` feature implements `Serialize` and `Deserialize` for [`Insertion`],
+//! [`OriginalLocation`] and [`InsertionDelta`].
+//!
 //! [DuckLogic]: https://ducklogic.org/
 //! [B3 JIT]: https://webkit.org/blog/5852/introducing-the-b3-jit-compiler/
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 #![deny(missing_docs)]
-use std::fmt::Debug;
-use std::iter::{ExactSizeIterator, FromIterator};
-use std::ops::Range;
+extern crate alloc;
+
+#[cfg(feature = "allocator_api")]
+use alloc::alloc::{Allocator, Global};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::iter::{ExactSizeIterator, FromIterator};
+use core::mem;
+use core::ops::{DerefMut, Range};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+/// Names a type that's generic over its allocator when the `allocator_api` feature is enabled,
+/// and always uses the global allocator otherwise.
+///
+/// For example, `alloc_in!(Vec<T> in A)` is `Vec<T, A>` with the feature and `Vec<T>` without it.
+/// The matching generic parameter should be declared as `#[cfg(feature = "allocator_api")] A: Allocator`.
+#[cfg(feature = "allocator_api")]
+macro_rules! alloc_in {
+    ($name:ident<$lt:lifetime, $($param:ty),*> in $alloc:ty) => { $name<$lt, $($param,)* $alloc> };
+    ($name:ident<$($param:ty),*> in $alloc:ty) => { $name<$($param,)* $alloc> };
+}
+#[cfg(not(feature = "allocator_api"))]
+macro_rules! alloc_in {
+    ($name:ident<$lt:lifetime, $($param:ty),*> in $alloc:ty) => { $name<$lt, $($param),*> };
+    ($name:ident<$($param:ty),*> in $alloc:ty) => { $name<$($param),*> };
+}
 
+mod checked;
+mod edit;
+mod lazy;
 mod shift;
+mod sort;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use self::checked::CheckedInsertionSet;
+pub use self::edit::EditSet;
+pub use self::lazy::LazyInsertionSet;
+pub use self::shift::{BulkDeleter, BulkShifter};
+pub use self::sort::{
+    HybridSortStrategy, InsertionSortStrategy, SortStrategy, StdStableSortStrategy,
+};
 
-use self::shift::BulkShifter;
+/// A reasonable default threshold for [`InsertionSet::par_sort`].
+///
+/// Below this many insertions, the overhead of a parallel sort usually outweighs the benefit.
+#[cfg(feature = "rayon")]
+pub const PARALLEL_SORT_THRESHOLD: usize = 50_000;
 
 /// A value that is pending insertion
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Insertion<T> {
     /// Where in the original vector to insert this value.
     ///
@@ -50,8 +111,44 @@ impl<T> From<(usize, T)> for Insertion<T> {
 /// When multiple insertions at a
 ///
 /// See module documentation for an overview.
-pub struct InsertionSet<T> {
-    insertions: Vec<Insertion<T>>,
+///
+/// An `InsertionSet<T>` is `Send` whenever `T: Send` and `Sync` whenever `T: Sync`,
+/// just like the `Vec<T>` it will be applied to.
+///
+/// Forgetting to apply a set is a silent no-op, so with the `drop_check` feature enabled,
+/// debug builds panic if a non-empty set is dropped without being applied.
+/// Use [Self::discard] to throw away queued insertions on purpose.
+///
+/// With the nightly-only `allocator_api` feature, the queued insertions are stored
+/// using the allocator `A` (see [`InsertionSet::new_in`]).
+/// Only the bookkeeping for [Self::insert_many_same], [Self::insert_anchored], [Self::pad_to]
+/// and [Self::insert_seq] uses the global allocator, and only once those are actually used.
+#[must_use = "an InsertionSet does nothing unless applied"]
+pub struct InsertionSet<T, #[cfg(feature = "allocator_api")] A: Allocator = Global> {
+    insertions: alloc_in!(Vec<Insertion<T>> in A),
+    /// Groups of insertions that share a single element.
+    ///
+    /// These are lazily expanded into `insertions` by `sort`,
+    /// so they are always empty once the insertions are sorted.
+    shared: Vec<SharedInsertions<T>>,
+    /// Padding that is resolved against the length of the target in `apply`
+    padding: Option<Padding<T>>,
+    /// Insertions relative to the end of the target, in the order queued.
+    ///
+    /// The `index` of each is the offset from the end,
+    /// which is resolved against the length of the target in `apply`.
+    anchored: Vec<Insertion<T>>,
+    /// The explicit sequence numbers of each insertion, used to break ties between equal indices.
+    ///
+    /// This is empty unless `insert_seq` has been used,
+    /// in which case it has exactly one entry per insertion.
+    seqs: Vec<u64>,
+    /// Whether `insertions` are already known to be sorted,
+    /// which lets `sort` skip even checking the order.
+    ///
+    /// This is set by `sort` and preserved by `push_sorted`,
+    /// but cleared by everything else that queues insertions.
+    sorted: bool,
 }
 impl<T> InsertionSet<T> {
     /// Create a new InsertionSet
@@ -59,47 +156,398 @@ impl<T> InsertionSet<T> {
     pub fn new() -> Self {
         InsertionSet {
             insertions: Vec::new(),
+            shared: Vec::new(),
+            padding: None,
+            anchored: Vec::new(),
+            seqs: Vec::new(),
+            sorted: true,
         }
     }
-    /// Queue the specified insertion
+    /// Create a new InsertionSet with room for at least `capacity` insertions
     ///
-    /// If there are multiple insertions at the same index,
-    /// they will be applied in the order queued.
+    /// This avoids repeatedly reallocating when the number of insertions is known up front.
     #[inline]
-    pub fn push(&mut self, insertion: Insertion<T>) {
-        self.insertions.push(insertion)
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut result = InsertionSet::new();
+        result.insertions = Vec::with_capacity(capacity);
+        result
     }
-    /// Insert the element to be inserted before the given index
+    /// Build a set from a list of `(index, element)` tuples,
+    /// validating that every index is in bounds for a vector of `target_len` elements.
     ///
-    /// If multiple elements are queued to be inserted at the same index,
-    /// they will be applied in the original order queued.
-    #[inline]
-    pub fn insert(&mut self, index: usize, element: T) {
-        self.push(Insertion { index, element })
+    /// Returns an error describing the first out-of-bounds index.
+    /// Otherwise, the insertions are queued in the order given.
+    pub fn try_from_tuples(
+        tuples: Vec<(usize, T)>,
+        target_len: usize,
+    ) -> Result<Self, InsertionError> {
+        if let Some(&(index, _)) = tuples.iter().find(|&&(index, _)| index > target_len) {
+            return Err(InsertionError {
+                index,
+                len: target_len,
+            });
+        }
+        Ok(tuples.into_iter().collect())
     }
-    /// Apply all of the pending insertions against the specified vector,
-    /// returning the result
+    /// Compute the insertions needed to turn `old` into `new`,
+    /// assuming `new` only adds elements (without deleting or reordering any).
+    ///
+    /// Returns `None` if `new` can't be obtained from `old` purely by insertion.
+    /// The original elements are matched greedily (as early as possible),
+    /// so applying the result to `old` always gives exactly `new`.
+    pub fn from_diff(old: &[T], new: &[T]) -> Option<Self>
+    where
+        T: PartialEq + Clone,
+    {
+        let mut result = InsertionSet::new();
+        let mut remaining = old.iter().enumerate().peekable();
+        for element in new {
+            if remaining
+                .next_if(|&(_, original)| original == element)
+                .is_none()
+            {
+                let index = remaining.peek().map_or(old.len(), |&(index, _)| index);
+                result.insert(index, element.clone());
+            }
+        }
+        match remaining.next() {
+            Some(_) => None,
+            None => Some(result),
+        }
+    }
+    /// Gather insertions from a fallible source, then apply them all to the target.
+    ///
+    /// This is all-or-nothing: the source is fully consumed and every index is validated
+    /// before the target is touched, so if the source fails (or an index is out of bounds)
+    /// the target is left completely unchanged.
+    pub fn try_apply_from<E, I>(target: &mut Vec<T>, source: I) -> Result<(), ApplyError<E>>
+    where
+        I: IntoIterator<Item = Result<Insertion<T>, E>>,
+    {
+        let mut insertions = source
+            .into_iter()
+            .collect::<Result<InsertionSet<T>, E>>()
+            .map_err(ApplyError::Source)?;
+        insertions.try_apply(target).map_err(ApplyError::Insertion)
+    }
+    /// Queue a run of elements to be inserted before the given index
+    ///
+    /// The elements will appear contiguously in the result, in the order of the iterator
+    /// (after any insertions already queued at the same index).
+    pub fn insert_all<I: IntoIterator<Item = T>>(&mut self, index: usize, elements: I) {
+        let elements = elements.into_iter();
+        self.reserve(elements.size_hint().0);
+        self.extend(elements.map(|element| Insertion::new(index, element)));
+    }
+    /// Queue clones of the elements in the slice to be inserted before the given index
+    ///
+    /// Like [Self::insert_all], the clones appear contiguously in the result (in slice order).
     #[inline]
-    pub fn applied(mut self, mut target: Vec<T>) -> Vec<T> {
-        self.apply(&mut target);
+    pub fn insert_slice(&mut self, index: usize, elements: &[T])
+    where
+        T: Clone,
+    {
+        self.insert_all(index, elements.iter().cloned())
+    }
+    /// Queue copies of the same element to be inserted before each of the given indices
+    ///
+    /// This is stored compactly as a single element and a list of indices,
+    /// and the element is only cloned once the set is actually applied.
+    /// The relative order of these insertions (and any others at the same index)
+    /// is the same as if each copy had been queued individually with [`Self::insert`].
+    pub fn insert_many_same(&mut self, indices: &[usize], element: T)
+    where
+        T: Clone,
+    {
+        if indices.is_empty() {
+            return;
+        }
+        self.shared.push(SharedInsertions {
+            position: self.insertions.len(),
+            indices: indices.to_vec(),
+            element,
+            clone: T::clone,
+        })
+    }
+    /// Queue all the insertions from another set (targeting the same original vector)
+    /// after the ones already queued.
+    ///
+    /// Since queued order is preserved, insertions from `self` are applied before insertions
+    /// from `other` at the same index.
+    /// If `other` requested any padding, it replaces the padding of `self`.
+    pub fn merge(&mut self, mut other: InsertionSet<T>) {
+        let insertions = mem::take(&mut other.insertions);
+        let shared = mem::take(&mut other.shared);
+        let padding = other.padding.take();
+        let anchored = mem::take(&mut other.anchored);
+        let seqs = mem::take(&mut other.seqs);
+        let offset = self.insertions.len();
+        if !seqs.is_empty() || !self.seqs.is_empty() {
+            // Keep the sequence numbers parallel to the insertions
+            self.seqs.resize(offset, 0);
+            if seqs.is_empty() {
+                self.seqs.resize(offset + insertions.len(), 0);
+            } else {
+                self.seqs.extend(seqs);
+            }
+        }
+        self.sorted = false;
+        self.insertions.extend(insertions);
+        self.shared
+            .extend(shared.into_iter().map(|shared| SharedInsertions {
+                position: shared.position + offset,
+                ..shared
+            }));
+        self.anchored.extend(anchored);
+        if padding.is_some() {
+            self.padding = padding;
+        }
+    }
+    /// Sort the insertions and resolve them against a target of the specified length,
+    /// without permanently queueing the anchored insertions or padding.
+    ///
+    /// The location queries use this, so the set can still be applied to a target of any length.
+    /// Panics if any of the indices are out of bounds.
+    fn resolved(&mut self, target_len: usize) -> Resolved<'_, T> {
+        if let Err(error) = self.validate(target_len) {
+            panic!("{}", error);
+        }
+        self.sort();
+        if self.anchored.is_empty() && self.padding_count(target_len) == 0 {
+            Resolved::Queued(&self.insertions)
+        } else {
+            Resolved::Pending(self.resolved_refs(target_len))
+        }
+    }
+    /// Apply all of the pending insertions against a vector of a different element type,
+    /// converting each inserted element with [`Into`] and returning the result.
+    ///
+    /// Only the inserted elements are converted, the original elements are left as-is.
+    pub fn applied_converting<U>(mut self, mut target: Vec<U>) -> Vec<U>
+    where
+        T: Into<U>,
+    {
+        self.prepare(target.len());
+        apply_exact_insertions(
+            &mut target,
+            mem::take(&mut self.insertions)
+                .into_iter()
+                .rev()
+                .map(|insertion| Insertion::new(insertion.index, insertion.element.into())),
+        );
         target
     }
-    /// The number of insertions that are currently queued
+    /// Apply all of the pending insertions against a vector of a different element type,
+    /// mapping each original element through `map` in the same pass.
+    ///
+    /// The inserted elements are already the output type, so only the originals are mapped
+    /// (in their original order).
+    /// Since the element types differ, this builds the result in a fresh vector
+    /// instead of reusing the memory of the target.
+    ///
+    /// Panics if any of the insertion indices are out of bounds of the target.
+    pub fn apply_mapping<O, F>(mut self, target: Vec<O>, mut map: F) -> Vec<T>
+    where
+        F: FnMut(O) -> T,
+    {
+        self.prepare(target.len());
+        self.seqs.clear();
+        let mut result = Vec::with_capacity(target.len() + self.insertions.len());
+        let mut insertions = mem::take(&mut self.insertions).into_iter().peekable();
+        for (original_index, element) in target.into_iter().enumerate() {
+            while let Some(insertion) =
+                insertions.next_if(|insertion| insertion.index <= original_index)
+            {
+                result.push(insertion.element);
+            }
+            result.push(map(element));
+        }
+        result.extend(insertions.map(|insertion| insertion.element));
+        result
+    }
+    /// Check whether applying these insertions to the target would need to reallocate it,
+    /// because it doesn't have enough spare capacity.
+    ///
+    /// This accounts for any padding requested by [Self::pad_to].
     #[inline]
-    pub fn desired_insertions(&self) -> usize {
-        self.insertions.len()
+    pub fn will_reallocate(&self, target: &Vec<T>) -> bool {
+        target.capacity() - target.len() < self.required_room(target.len())
+    }
+    /// The number of elements that applying to a target of the specified length would add,
+    /// including any padding.
+    #[inline]
+    fn required_room(&self, target_len: usize) -> usize {
+        self.desired_insertions() + self.padding_count(target_len)
+    }
+    /// Check that applying these insertions to a target of the specified length
+    /// wouldn't grow it beyond `max_len`.
+    ///
+    /// This accounts for any padding requested by [Self::pad_to],
+    /// and is meant as a cheap guard before [Self::apply].
+    pub fn check_growth_budget(
+        &self,
+        target_len: usize,
+        max_len: usize,
+    ) -> Result<(), GrowthExceeded> {
+        let len = target_len + self.required_room(target_len);
+        if len > max_len {
+            return Err(GrowthExceeded { len, max_len });
+        }
+        Ok(())
+    }
+    /// The number of distinct indices that have queued insertions
+    ///
+    /// Each distinct index requires a separate shift of the original elements,
+    /// so this correlates with the cost of memory movement.
+    pub fn distinct_insertion_points(&mut self) -> usize {
+        self.sort();
+        let mut count = 0;
+        let mut last_index = None;
+        for insertion in &self.insertions {
+            if last_index != Some(insertion.index) {
+                count += 1;
+                last_index = Some(insertion.index);
+            }
+        }
+        count
+    }
+    /// Find the longest run of insertions whose (sorted) indices increase by exactly one,
+    /// returning the range of indices it covers.
+    ///
+    /// For example, insertions at `[0, 3, 4, 5, 9]` have a dense run covering `3..6`.
+    /// Repeated indices break a run, and ties are resolved in favor of the first run.
+    /// Returns an empty range if there are no insertions.
+    pub fn longest_dense_run(&mut self) -> Range<usize> {
+        self.sort();
+        let mut longest = 0..0;
+        let mut current: Option<Range<usize>> = None;
+        for insertion in &self.insertions {
+            let index = insertion.index;
+            let run = match current {
+                Some(run) if run.end == index => run.start..index + 1,
+                _ => index..index + 1,
+            };
+            if run.len() > longest.len() {
+                longest = run.clone();
+            }
+            current = Some(run);
+        }
+        longest
+    }
+    /// The largest final position that any inserted element will occupy,
+    /// or `None` if there are no insertions.
+    ///
+    /// Panics if any insertion index is out of bounds of `target_len`.
+    pub fn max_inserted_position(&mut self, target_len: usize) -> Option<usize> {
+        let resolved = self.resolved(target_len);
+        let last = resolved.len().checked_sub(1)?;
+        // Every other insertion is placed before the last one
+        Some(resolved.index(last) + last)
+    }
+    /// How far the last original element will be shifted to the right
+    ///
+    /// This is the number of insertions that land before the end of the vector,
+    /// since trailing insertions at `target_len` are appended after it.
+    ///
+    /// Panics if any insertion index is out of bounds of `target_len`.
+    pub fn tail_shift(&mut self, target_len: usize) -> usize {
+        self.resolved(target_len).count_before(target_len)
+    }
+    /// The number of entries in the location map for a target of the specified length.
+    ///
+    /// This is exactly the number of pairs produced by [Self::list_updated_locations]
+    /// (or [Self::iter_updated_locations]), which is useful to pre-allocate a buffer for them.
+    /// It includes any anchored insertions and padding.
+    #[inline]
+    pub fn location_map_len(&self, target_len: usize) -> usize {
+        target_len + self.required_room(target_len)
+    }
+    /// Check whether applying either set to a source of `source_len` elements
+    /// would give the same result.
+    ///
+    /// This compares the placements (final position and element) of the inserted elements,
+    /// rather than the full result, so it doesn't need the source elements at all.
+    /// Sets that queue the same insertions in a different order (at distinct indices)
+    /// are considered equal.
+    ///
+    /// Panics if any insertion index is out of bounds of `source_len`.
+    pub fn effects_equal(&mut self, other: &mut InsertionSet<T>, source_len: usize) -> bool
+    where
+        T: PartialEq,
+    {
+        let resolved = self.resolved(source_len);
+        let other = other.resolved(source_len);
+        // With the same number of insertions, each final position is determined by the index
+        resolved.len() == other.len() && resolved.iter().eq(other.iter())
     }
     /// List the updated locations of all the elements (both original and newly inserted).
     ///
     /// See [Self::compute_updated_locations] for details
     pub fn list_updated_locations(&mut self, target: &[T]) -> Vec<(OriginalLocation, usize)> {
-        let mut result = Vec::with_capacity(target.len() + self.desired_insertions());
-        self.compute_updated_locations(target, |original, updated| {
-            result.push((original, updated))
-        });
-        result.sort_by_key(|&(_, updated)| updated);
+        let mut result = Vec::new();
+        self.list_updated_locations_into(target, &mut result);
         result
     }
+    /// List the updated locations of all the elements into the specified buffer,
+    /// sorted by their updated location.
+    ///
+    /// The buffer is cleared first, so its memory can be reused across many calls.
+    /// See [Self::list_updated_locations] for details
+    pub fn list_updated_locations_into(
+        &mut self,
+        target: &[T],
+        out: &mut Vec<(OriginalLocation, usize)>,
+    ) {
+        out.clear();
+        out.reserve(self.location_map_len(target.len()));
+        self.compute_updated_locations(target, |original, updated| out.push((original, updated)));
+        out.sort_by_key(|&(_, updated)| updated);
+    }
+    /// Compute the original location of every element in the result,
+    /// indexed by its final position.
+    ///
+    /// This is the inverse of [Self::list_updated_locations].
+    pub fn compute_original_locations(&mut self, target: &[T]) -> Vec<OriginalLocation> {
+        let len = self.location_map_len(target.len());
+        self.locations_in_window(target.len(), 0..len)
+            .into_iter()
+            .map(|(original, _)| original)
+            .collect()
+    }
+    /// Compute the final index of the original element at `original_index`,
+    /// without building the full location map.
+    ///
+    /// This is the same location [Self::list_updated_locations] gives for
+    /// `OriginalLocation::Original(original_index)`, found with a binary search.
+    ///
+    /// Panics if `original_index` is out of bounds of the target,
+    /// or if any of the insertion indexes are.
+    pub fn final_index_of_original(&mut self, original_index: usize, target: &[T]) -> usize {
+        assert!(
+            original_index < target.len(),
+            "Invalid original index {} >= len {}",
+            original_index,
+            target.len()
+        );
+        // Insertions at the same index are placed before the original element
+        original_index + self.resolved(target.len()).count_before(original_index + 1)
+    }
+    /// Compute the final index of the insertion with the specified id,
+    /// without building the full location map.
+    ///
+    /// Like [OriginalLocation::Insertion], the id is the insertion's position in sorted order.
+    ///
+    /// Panics if `insertion_id` is out of range,
+    /// or if any of the insertion indexes are out of bounds of the target.
+    pub fn final_index_of_insertion(&mut self, insertion_id: usize, target: &[T]) -> usize {
+        let resolved = self.resolved(target.len());
+        let count = resolved.len();
+        match resolved.get(insertion_id) {
+            // Each insertion is preceded by exactly `insertion_id` other insertions
+            Some((index, _)) => index + insertion_id,
+            None => panic!("Invalid insertion id {} >= count {}", insertion_id, count),
+        }
+    }
     /// Compute the updated locations of all the elements (both original and newly inserted).
     ///
     /// Assumes this set of insertions are being applied against the specified slice,
@@ -111,22 +559,18 @@ impl<T> InsertionSet<T> {
     where
         F: FnMut(OriginalLocation, usize),
     {
-        self.sort();
+        let resolved = self.resolved(target.len());
+        let count = resolved.len();
         compute_updated_locations(
             target,
-            self.insertions
-                .iter()
-                .rev()
-                .map(|insertion| insertion.index),
+            (0..count).rev().map(|id| resolved.index(id)),
             |original, updated| {
                 func(
                     match original {
                         OriginalLocation::Original(_) => original,
                         OriginalLocation::Insertion(reversed_index) => {
                             // Convert the reversed insertion index back to the original one
-                            OriginalLocation::Insertion(
-                                self.insertions.len() - (reversed_index + 1),
-                            )
+                            OriginalLocation::Insertion(count - (reversed_index + 1))
                         }
                     },
                     updated,
@@ -134,234 +578,5003 @@ impl<T> InsertionSet<T> {
             },
         )
     }
-    /// Applies all the insertions to the specified target vector.
+    /// Applies all the insertions, but only if the target already has enough spare capacity.
     ///
-    /// This reuses the Vector's existing memory if possible,
-    /// but may require a reallocation (due to new values)
+    /// This guarantees the apply never allocates.
+    /// If it would need to, the target is left untouched and an error is returned instead.
+    pub fn try_apply_no_alloc(&mut self, target: &mut Vec<T>) -> Result<(), NeedsAllocation> {
+        if self.will_reallocate(target) {
+            return Err(NeedsAllocation {
+                required: self.required_room(target.len()),
+                available: target.capacity() - target.len(),
+            });
+        }
+        self.apply(target);
+        Ok(())
+    }
+    /// Applies all the insertions to a [`SmallVec`](smallvec::SmallVec),
+    /// exactly like [Self::apply].
     ///
-    /// The average runtime of this function is `O(n + m)`,
-    /// where `n` is the number of existing elements and `m` is the number of insertions.
-    /// The worst case running time is `O((k * log(k))` where `k = n + m`.
-    pub fn apply(&mut self, target: &mut Vec<T>) {
-        self.sort();
-        apply_bulk_insertions(target, PoppingIter(&mut self.insertions));
+    /// The target stays inline if it has room for all the insertions,
+    /// and otherwise spills to the heap (once) just like [`SmallVec::reserve`](smallvec::SmallVec::reserve).
+    ///
+    /// This requires the `smallvec` feature.
+    #[cfg(feature = "smallvec")]
+    pub fn apply_to_smallvec<A>(&mut self, target: &mut smallvec::SmallVec<A>)
+    where
+        A: smallvec::Array<Item = T>,
+    {
+        if let Err(error) = self.validate(target.len()) {
+            panic!("{}", error);
+        }
+        self.resolve_pending(target.len());
+        let len = target.len();
+        let required = self.desired_insertions();
+        target.reserve(required);
+        unsafe {
+            // Leak rather than double drop if anything panics while elements are moving
+            target.set_len(0);
+            let buf = core::slice::from_raw_parts_mut(
+                target.as_mut_ptr().cast::<mem::MaybeUninit<T>>(),
+                len + required,
+            );
+            match self.apply_into_slice(buf, len) {
+                Ok(new_len) => target.set_len(new_len),
+                Err(_) => unreachable!("Already reserved room for the insertions"),
+            }
+        }
     }
-    fn sort(&mut self) {
+    /// Applies all the insertions into a fixed buffer, whose first `initialized_len` elements
+    /// are initialized and whose remaining elements are spare room.
+    ///
+    /// This performs the same reverse shifting as [Self::apply], but never allocates.
+    /// Returns the new initialized length of the buffer.
+    /// If there isn't enough spare room for all the insertions (including any padding),
+    /// both the buffer and the set are left untouched and an error is returned instead.
+    ///
+    /// Panics if any of the insertion indices are out of bounds of `initialized_len`
+    /// (before anything is moved).
+    pub fn apply_into_slice(
+        &mut self,
+        buf: &mut [mem::MaybeUninit<T>],
+        initialized_len: usize,
+    ) -> Result<usize, NeedsAllocation> {
+        assert!(
+            initialized_len <= buf.len(),
+            "Invalid initialized len {} > buffer len {}",
+            initialized_len,
+            buf.len()
+        );
+        if let Err(error) = self.validate(initialized_len) {
+            panic!("{}", error);
+        }
+        // Check before resolving anything, so the set is untouched on error
+        let required = self.required_room(initialized_len);
+        let available = buf.len() - initialized_len;
+        if required > available {
+            return Err(NeedsAllocation {
+                required,
+                available,
+            });
+        }
+        self.prepare(initialized_len);
         /*
-         * In many scenarios, the input is mostly sorted.
-         * In those cases, insertion sort may be better than std::slice::sort.
-         * When the array is already mostly sorted, insertion sort has average running time `O(nk)`,
-         * where `k` is the average distance of each element from its proper position.
-         *
-         * Previous versions of this code used insertion sort for this reason,
-         * because we expected the input to be mostly sorted in the compiler.
-         * However, now that we are a library, we want to support as many use cases as possible.
-         * The documentation guarantees linear behavior,
-         * and we don't want to have quadratic blowup if the user has unexpected input.
-         * See issue #1 for details.
-         *
-         * The big advantage of insertion sort over std::slice::sort is that it avoids allocation.
-         * If the allocation in std::sort becomes a significant performance overhead,
-         * we could try and optimistically perform insertion sort,
-         * falling back to stdlib sort on input that is not already mostly-sorted.
-         * Alternatively, we could try reusing memory or offering the user a choice.
+         * Swapping the original elements with the uninitialized memory in the middle
+         * moves each of them exactly once, without needing any unsafe code.
          */
-        self.insertions.sort_by_key(|insertion| insertion.index);
+        let mut len = initialized_len;
+        let mut shifted_start = initialized_len + required;
+        for Insertion { index, element } in self.drain_sorted() {
+            for original in (index..len).rev() {
+                shifted_start -= 1;
+                buf.swap(original, shifted_start);
+            }
+            len = index;
+            shifted_start -= 1;
+            buf[shifted_start].write(element);
+        }
+        debug_assert_eq!(len, shifted_start);
+        Ok(initialized_len + required)
     }
-}
-impl<T> FromIterator<Insertion<T>> for InsertionSet<T> {
-    #[inline]
-    fn from_iter<I: IntoIterator<Item = Insertion<T>>>(iter: I) -> Self {
-        InsertionSet {
-            insertions: iter.into_iter().collect(),
+    /// Applies all the insertions, returning the range of the result
+    /// occupied by the inserted elements if they are contiguous.
+    ///
+    /// The inserted elements are contiguous whenever they all share a single index,
+    /// which includes the common case of pure appends (where every index is `target.len()`).
+    /// If they are interspersed between original elements, this returns `None`
+    /// (although the insertions are still applied).
+    pub fn apply_returning_appended(&mut self, target: &mut Vec<T>) -> Option<Range<usize>> {
+        self.prepare(target.len());
+        let inserted = self.insertions.len();
+        let contiguous = match (self.insertions.first(), self.insertions.last()) {
+            (Some(first), Some(last)) if first.index == last.index => {
+                Some(first.index..first.index + inserted)
+            }
+            (Some(_), Some(_)) => None,
+            _ => Some(target.len()..target.len()),
+        };
+        self.apply(target);
+        contiguous
+    }
+    /// Consume the set, returning the `(index, element)` pairs sorted by index.
+    ///
+    /// The sort is stable, so pairs with the same index are in the order they were queued.
+    /// Like [Self::into_sorted_vec], anchored insertions and padding aren't included.
+    pub fn into_sorted_tuples(self) -> Vec<(usize, T)> {
+        self.into_sorted_vec()
+            .into_iter()
+            .map(|insertion| (insertion.index, insertion.element))
+            .collect()
+    }
+    /// Consume the set, returning the insertions sorted by index.
+    ///
+    /// The sort is stable, so insertions with the same index are in the order they were queued.
+    /// This is the opposite order of [Self::into_reverse_sorted].
+    /// Anchored insertions and padding depend on the target, so they aren't included.
+    pub fn into_sorted_vec(mut self) -> Vec<Insertion<T>> {
+        self.sort();
+        self.take_queued()
+    }
+    /// Consume the set, yielding the insertions in reverse sorted order.
+    ///
+    /// This is exactly the order [`apply_bulk_insertions`] expects,
+    /// so it can be fed straight into it (possibly after some custom processing).
+    /// Like [Self::into_iter](IntoIterator::into_iter), anchored insertions and padding
+    /// depend on the target, so they aren't included.
+    pub fn into_reverse_sorted(self) -> impl ExactSizeIterator<Item = Insertion<T>> {
+        self.into_sorted_vec().into_iter().rev()
+    }
+    /// Prepare a [`BulkShifter`] with room for all the insertions,
+    /// returning it along with the sorted insertions (in ascending order).
+    ///
+    /// This gives full manual control over the shift loop that [`Self::apply`] performs.
+    /// To reproduce `apply`, pop insertions off the back of the buffer and for each one
+    /// call [`BulkShifter::shift_original`] with its index, then [`BulkShifter::push_shifted`] with its element,
+    /// before finally calling [`BulkShifter::finish`].
+    /// Any anchored insertions and padding are resolved against the target first.
+    ///
+    /// Panics if any of the insertion indices are out of bounds of the target.
+    pub fn into_shifter(mut self, target: &mut Vec<T>) -> (BulkShifter<'_, T>, Vec<Insertion<T>>) {
+        self.prepare(target.len());
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        (shifter, mem::take(&mut self.insertions))
+    }
+    /// Applies all the insertions to a copy-on-write vector behind an [`Arc`].
+    ///
+    /// This uses [`Arc::make_mut`], so the vector is only cloned if it's shared.
+    /// Otherwise, the insertions are applied in-place.
+    pub fn apply_arc(&mut self, target: &mut Arc<Vec<T>>)
+    where
+        T: Clone,
+    {
+        self.apply(Arc::make_mut(target))
+    }
+    /// Applies all the insertions to a double-ended queue,
+    /// with exactly the same ordering and bounds checking as [Self::apply].
+    ///
+    /// The ring buffer is converted into a vector in-place (reusing its memory),
+    /// which costs `O(n)` to make the elements contiguous if it has wrapped around.
+    /// So the total running time is still `O(n + m)` on average.
+    pub fn apply_to_deque(&mut self, target: &mut VecDeque<T>) {
+        // Validate before taking the elements, so they're never lost on panic
+        if let Err(error) = self.validate(target.len()) {
+            panic!("{}", error);
         }
+        let mut elements = Vec::from(mem::take(target));
+        self.apply(&mut elements);
+        *target = VecDeque::from(elements);
     }
-}
-impl<T> FromIterator<(usize, T)> for InsertionSet<T> {
-    #[inline]
-    fn from_iter<I: IntoIterator<Item = (usize, T)>>(iter: I) -> Self {
-        iter.into_iter().map(Insertion::from).collect()
+    /// Applies all the insertions like [Self::apply],
+    /// but includes the offending element in the panic message if an index is out of bounds.
+    ///
+    /// All the indices are validated before the target is modified.
+    pub fn apply_debug(&mut self, target: &mut Vec<T>)
+    where
+        T: Debug,
+    {
+        self.sort();
+        // The offset of an anchored insertion has the same bound as an index
+        if let Some(invalid) = self
+            .insertions
+            .iter()
+            .chain(&self.anchored)
+            .find(|insertion| insertion.index > target.len())
+        {
+            panic!(
+                "Invalid insertion index {} > len {} for element {:?}",
+                invalid.index,
+                target.len(),
+                invalid.element
+            );
+        }
+        self.apply(target);
     }
-}
-impl<T> Default for InsertionSet<T> {
-    #[inline]
-    fn default() -> Self {
-        InsertionSet::new()
+    /// Validate each inserted element against its would-be predecessor, then apply all the insertions.
+    ///
+    /// The callback receives each inserted element along with the element that will precede it
+    /// in the result (either an original element or an earlier insertion at the same index),
+    /// or `None` if it will be the first element.
+    /// Validation happens in a dry-run before anything is moved,
+    /// so if any element is rejected the target is left untouched
+    /// and the index of the offending insertion is returned.
+    pub fn apply_validated<F>(&mut self, target: &mut Vec<T>, mut validate: F) -> Result<(), usize>
+    where
+        F: FnMut(&T, Option<&T>) -> bool,
+    {
+        let mut previous: Option<(usize, &T)> = None;
+        for (index, element) in self.resolved(target.len()).iter() {
+            let predecessor = match previous {
+                Some((previous_index, previous)) if previous_index == index => Some(previous),
+                _ if index > 0 => Some(&target[index - 1]),
+                _ => None,
+            };
+            if !validate(element, predecessor) {
+                return Err(index);
+            }
+            previous = Some((index, element));
+        }
+        self.apply(target);
+        Ok(())
     }
-}
-
-struct PoppingIter<'a, T: 'a>(&'a mut Vec<T>);
-impl<'a, T> Iterator for PoppingIter<'a, T> {
-    type Item = T;
-
+    /// Applies all the insertions to a vector behind a smart pointer or guard.
+    ///
+    /// This is equivalent to [Self::apply], but works through anything that derefs to a `Vec<T>`.
     #[inline]
-    fn next(&mut self) -> Option<T> {
-        self.0.pop()
+    pub fn apply_deref<D>(&mut self, target: &mut D)
+    where
+        D: DerefMut<Target = Vec<T>>,
+    {
+        self.apply(target)
     }
-
-    #[inline]
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.0.len(), Some(self.0.len()))
+    /// Applies all the insertions like [Self::apply],
+    /// while reserving `headroom` additional elements of spare capacity in the target.
+    ///
+    /// This is useful when many small batches are applied to the same growing vector,
+    /// since it amortizes the cost of reallocation across future applies.
+    /// The headroom only affects the capacity, never the length of the result.
+    pub fn apply_with_headroom(&mut self, target: &mut Vec<T>, headroom: usize) {
+        self.prepare(target.len());
+        let shifter = BulkShifter::with_reserve_hint(target, self.insertions.len(), headroom);
+        shift_insertions(shifter, self.drain_sorted(), |_| {});
     }
-}
-impl<'a, T> ExactSizeIterator for PoppingIter<'a, T> {}
-
-/// Applies all the specified insertions into the target vector.
-///
-/// The insertion iterator must be sorted in reverse order and give the proper size for its `ExactSizeIterator`.
-/// Violating these constraints will never cause undefined behavior,
-/// since internally we use the completely safe `BulkShifter` abstraction.
-pub fn apply_bulk_insertions<T, I>(target: &mut Vec<T>, mut insertions: I)
-where
-    I: Iterator<Item = Insertion<T>>,
-    I: ExactSizeIterator,
-{
-    let mut shifter = BulkShifter::new(target, insertions.len());
-    /*
-     * We perform insertions in reverse order to reduce moving memory,
-     * and ensure that the function is panic safe.
-     *
-     * For example, given the vector
-     * and the InsertionSet `[(0, 0), (1, 2), (1, 3) (4, 9)]`:
-     *
-     * Since the first (working backwards) insertion is `(4, 9)`,
-     * we need to to shift all elements after our first insertion
-     * to the left 4 places:
-     * `[1, 4, 5, 7, undef, undef, undef, undef, 11]`.
-     * The element `11` will never need to be moved again,
-     * since we've already made room for all future insertions.
-     *
-     * Next, we perform our first insertion (4, 9) at the last `undef` element:
-     * `[1, 4, 5, 7, undef, undef, undef, 9, 11]`.
-     * We only have 3 insertions left to perform,
-     * so all future shifts will only need to move over two.
-     * Then, we handle the group of insertions `[(1, 2), [(1, 3)]`,
-     * and shift all elements past index 1 to the left 3 spaces:
-     * [1, undef, undef, undef, 4, 5, 7, 9, 11].
-     * Then we perform our desired insertions at index 1:
-     * [1, undef, 2, 3, 4, 9, 11].
-     * Finally, we perform the same process for the final insertion (0, 0),
-     * resulting in the desired result: [0, 1, 2, 3, 4, 9, 11].
-     */
+    /// Applies all the insertions within a sub-range of a larger target vector.
+    ///
+    /// The insertion indices are relative to `region.start`, and may be at most `region.len()`
+    /// (inserting at the end of the region).
+    /// The region grows to make room for the new elements,
+    /// so all the fixed elements after `region.end` are shifted right by the number of insertions.
+    /// The elements before `region.start` are never moved.
+    /// Anchored insertions and padding are resolved against the region, as if it were the whole target.
+    pub fn apply_within(&mut self, target: &mut Vec<T>, region: Range<usize>) {
+        assert!(
+            region.start <= region.end && region.end <= target.len(),
+            "Invalid region {:?} for len {}",
+            region,
+            target.len()
+        );
+        self.prepare(region.len());
+        for insertion in &mut self.insertions {
+            insertion.index += region.start;
+        }
+        apply_exact_insertions(target, self.drain_sorted());
+    }
+    /// Applies all the insertions, translating their indices through `to_real_index` first.
+    ///
+    /// This is useful when the insertion indices were computed against
+    /// a filtered view of the target, rather than the target itself.
+    /// The translation is invoked once for each insertion, in sorted order.
+    ///
+    /// The translation should be monotonic (never decreasing),
+    /// so that the sorted order of the logical indices matches the physical ones.
+    /// Anchored insertions and padding are resolved against the target itself,
+    /// so they are never translated.
+    pub fn apply_via_index_map<F>(&mut self, target: &mut Vec<T>, mut to_real_index: F)
+    where
+        F: FnMut(usize) -> usize,
+    {
+        self.sort();
+        for insertion in &mut self.insertions {
+            insertion.index = to_real_index(insertion.index);
+        }
+        // A translation that isn't actually monotonic could break the order
+        self.sorted = false;
+        self.apply(target);
+    }
+    /// Applies all the insertions, returning a compact delta that can
+    /// reconstruct the result from another copy of the original vector.
+    ///
+    /// See [`InsertionDelta`] for details.
+    pub fn apply_recording_delta(mut self, target: &mut Vec<T>) -> InsertionDelta<T>
+    where
+        T: Clone,
+    {
+        self.prepare(target.len());
+        let original_len = target.len();
+        let placements = self
+            .insertions
+            .iter()
+            .enumerate()
+            .map(|(id, insertion)| (insertion.index + id, insertion.element.clone()))
+            .collect();
+        self.apply(target);
+        InsertionDelta {
+            placements,
+            original_len,
+        }
+    }
+    /// Applies all the insertions, returning the number of original elements preserved
+    /// and the number of insertions placed.
+    ///
+    /// Both are counted while shifting: the preserved originals are the ones that were moved
+    /// plus the ones before the first insertion (which are left in place).
+    /// The two counts are checked to add up to the new length in debug builds.
+    pub fn apply_with_counts(&mut self, target: &mut Vec<T>) -> (usize, usize) {
+        self.prepare(target.len());
+        let mut moved = 0;
+        let mut placed = 0;
+        // Everything before the last insertion placed (the first in sorted order) is untouched
+        let mut untouched = target.len();
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        shift_insertions(shifter, self.drain_sorted(), |event| match event {
+            ShiftEvent::Shift { count, .. } => moved += count,
+            ShiftEvent::Push { index } => {
+                placed += 1;
+                untouched = index;
+            }
+        });
+        let preserved = moved + untouched;
+        debug_assert_eq!(preserved + placed, target.len());
+        (preserved, placed)
+    }
+    /// Applies all the insertions, returning the final position of each inserted element
+    /// indexed by the order it was queued in.
+    ///
+    /// Any anchored insertions and padding are counted after everything else.
+    /// The positions are computed while sorting, so this doesn't need a separate pass
+    /// like [Self::list_updated_locations].
+    pub fn apply_tracking(&mut self, target: &mut Vec<T>) -> Vec<usize> {
+        if let Err(error) = self.validate(target.len()) {
+            panic!("{}", error);
+        }
+        self.resolve_pending(target.len());
+        self.expand_shared();
+        let seqs = mem::take(&mut self.seqs);
+        let mut queued = mem::take(&mut self.insertions)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        // Sort the queued ids instead of the insertions, so we know where each one ends up
+        let mut order = (0..queued.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|&id| {
+            let index = queued[id].as_ref().unwrap().index;
+            (index, seqs.get(id).copied().unwrap_or(0))
+        });
+        let mut positions = alloc::vec![0; queued.len()];
+        let sorted = order
+            .iter()
+            .enumerate()
+            .map(|(sorted_id, &id)| {
+                let insertion = queued[id].take().unwrap();
+                // Each insertion is preceded by exactly `sorted_id` other insertions
+                positions[id] = insertion.index + sorted_id;
+                insertion
+            })
+            .collect::<Vec<_>>();
+        apply_exact_insertions(target, sorted.into_iter().rev());
+        positions
+    }
+    /// Applies all the insertions, reporting how the memory of the target was affected.
+    ///
+    /// This is useful for deciding whether it's worth reserving room in the target up front.
+    /// See [`ApplyStats`] for details.
+    pub fn apply_reporting(&mut self, target: &mut Vec<T>) -> ApplyStats {
+        self.prepare(target.len());
+        let old_capacity = target.capacity();
+        let mut elements_moved = 0;
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        shift_insertions(shifter, self.drain_sorted(), |event| {
+            if let ShiftEvent::Shift { count, .. } = event {
+                elements_moved += count;
+            }
+        });
+        // The capacity only ever changes if `reserve` reallocates
+        let new_capacity = target.capacity();
+        ApplyStats {
+            reallocated: new_capacity != old_capacity,
+            old_capacity,
+            new_capacity,
+            elements_moved,
+        }
+    }
+    /// Applies all the insertions, invoking the observer on each original element that was moved.
+    ///
+    /// The observer receives the element along with its old and new index,
+    /// and is only invoked once all the memory has been moved
+    /// (so the element is already at its new address).
+    /// Elements are observed in ascending order of their index.
+    /// Original elements that didn't need to move (before the first insertion) are never observed.
+    pub fn apply_observing_moves<F>(&mut self, target: &mut Vec<T>, mut observer: F)
+    where
+        F: FnMut(&mut T, usize, usize),
+    {
+        self.prepare(target.len());
+        let mut shifts = Vec::new();
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        shift_insertions(shifter, self.drain_sorted(), |event| {
+            if let ShiftEvent::Shift { start, count, to } = event {
+                shifts.push((start, count, to));
+            }
+        });
+        // Shifts are performed in reverse order
+        for &(start, count, to) in shifts.iter().rev() {
+            for offset in 0..count {
+                observer(&mut target[to + offset], start + offset, to + offset);
+            }
+        }
+    }
+    /// Applies all the insertions, returning the final positions of the inserted elements
+    /// in ascending order.
+    ///
+    /// The positions are recorded while shifting, without computing the full location map.
+    pub fn apply_yielding_changes(mut self, target: &mut Vec<T>) -> impl Iterator<Item = usize> {
+        self.prepare(target.len());
+        let mut positions = Vec::with_capacity(self.insertions.len());
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        shift_insertions(shifter, self.drain_sorted(), |event| {
+            if let ShiftEvent::Push { index } = event {
+                positions.push(index);
+            }
+        });
+        // Insertions are pushed in reverse order
+        positions.into_iter().rev()
+    }
+    /// Applies all the insertions, returning a token that can later undo them
+    /// with [`revert`].
+    ///
+    /// The token only records the final positions of the inserted elements,
+    /// so it stays cheap no matter how large the target is.
+    pub fn apply_transactional(mut self, target: &mut Vec<T>) -> RevertToken {
+        self.prepare(target.len());
+        let mut positions = Vec::with_capacity(self.insertions.len());
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        shift_insertions(shifter, self.drain_sorted(), |event| {
+            if let ShiftEvent::Push { index } = event {
+                positions.push(index);
+            }
+        });
+        // Insertions are pushed in reverse order, which is exactly what we want
+        RevertToken { positions }
+    }
+    /// Applies all the insertions, building an index from each final position
+    /// to the original location of the element there.
+    ///
+    /// The index is built while shifting, so this only needs a single walk.
+    /// If `insertions_only` is true, the map only contains the inserted elements,
+    /// which keeps it small when the original elements aren't interesting.
+    ///
+    /// This requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn apply_building_index(
+        mut self,
+        target: &mut Vec<T>,
+        insertions_only: bool,
+    ) -> HashMap<usize, OriginalLocation> {
+        self.prepare(target.len());
+        let original_len = target.len();
+        let mut index = HashMap::with_capacity(if insertions_only {
+            self.insertions.len()
+        } else {
+            original_len + self.insertions.len()
+        });
+        // Insertions are pushed in reverse order
+        let mut next_insertion_id = self.insertions.len();
+        let mut moved = 0;
+        let shifter = BulkShifter::new(target, self.insertions.len());
+        shift_insertions(shifter, self.drain_sorted(), |event| match event {
+            ShiftEvent::Shift { start, count, to } => {
+                moved += count;
+                if !insertions_only {
+                    index.extend(
+                        (0..count).map(|offset| {
+                            (to + offset, OriginalLocation::Original(start + offset))
+                        }),
+                    );
+                }
+            }
+            ShiftEvent::Push { index: updated } => {
+                next_insertion_id -= 1;
+                index.insert(updated, OriginalLocation::Insertion(next_insertion_id));
+            }
+        });
+        if !insertions_only {
+            // The leading elements are never moved
+            index.extend((0..original_len - moved).map(|i| (i, OriginalLocation::Original(i))));
+        }
+        index
+    }
+    /// Remap the result of a `binary_search` against the original slice,
+    /// giving the equivalent result against the vector with these insertions applied.
+    ///
+    /// An `Ok(i)` refers to the original element at index `i`,
+    /// and is remapped to the updated location of that element.
+    ///
+    /// An `Err(i)` refers to the gap just before the original element `i`.
+    /// Any insertions that land exactly at `i` will fill that gap,
+    /// so the remapped `Err` points to the start of the gap (before all of them).
+    /// An `Err(target_len)` still refers to the end of the vector.
+    ///
+    /// Panics if the index is out of bounds for a slice of `target_len` elements.
+    pub fn remap_search_result(
+        &mut self,
+        found: Result<usize, usize>,
+        target_len: usize,
+    ) -> Result<usize, usize> {
+        let resolved = self.resolved(target_len);
+        match found {
+            Ok(index) => {
+                assert!(
+                    index < target_len,
+                    "Invalid index {} >= len {}",
+                    index,
+                    target_len
+                );
+                Ok(index + resolved.count_before(index + 1))
+            }
+            Err(index) => {
+                assert!(
+                    index <= target_len,
+                    "Invalid index {} > len {}",
+                    index,
+                    target_len
+                );
+                Err(index + resolved.count_before(index))
+            }
+        }
+    }
+    /// Compute the updated locations of all the elements,
+    /// when these insertions are combined with a set of deletions.
+    ///
+    /// The `deletions` are original indices that will be removed,
+    /// and must be sorted in strictly ascending order.
+    /// The callback is invoked with `None` for each deleted original element,
+    /// and with the updated location for everything else (including the inserted elements).
+    /// It is invoked in ascending order of the (original) positions.
+    ///
+    /// Deletions are applied before insertions, so an insertion at the index of a deleted element
+    /// takes its place (before the next original element).
+    ///
+    /// Panics if any of the insertion or deletion indices are out of bounds of `target_len`.
+    pub fn compute_updated_locations_with_deletions<F>(
+        &mut self,
+        target_len: usize,
+        deletions: &[usize],
+        mut func: F,
+    ) where
+        F: FnMut(OriginalLocation, Option<usize>),
+    {
+        assert!(
+            deletions.windows(2).all(|pair| pair[0] < pair[1]),
+            "Deletions must be strictly ascending"
+        );
+        if let Some(&last) = deletions.last() {
+            assert!(
+                last < target_len,
+                "Invalid deletion index {} >= len {}",
+                last,
+                target_len
+            );
+        }
+        let resolved = self.resolved(target_len);
+        let mut updated = 0;
+        let mut insertions = resolved.iter().enumerate().peekable();
+        let mut deletions = deletions.iter().peekable();
+        for original_index in 0..=target_len {
+            while let Some((id, _)) = insertions.next_if(|&(_, (index, _))| index == original_index)
+            {
+                func(OriginalLocation::Insertion(id), Some(updated));
+                updated += 1;
+            }
+            if original_index == target_len {
+                break;
+            }
+            if deletions.next_if_eq(&&original_index).is_some() {
+                func(OriginalLocation::Original(original_index), None);
+            } else {
+                func(OriginalLocation::Original(original_index), Some(updated));
+                updated += 1;
+            }
+        }
+    }
+    /// Visit every element of the result in final order, without actually applying the insertions.
+    ///
+    /// The callback receives the original location of each element,
+    /// along with a reference to it (from either the `source` or the queued insertions).
+    ///
+    /// Panics if any of the insertion indices are out of bounds of the source.
+    pub fn for_each_final<F>(&mut self, source: &[T], mut func: F)
+    where
+        F: FnMut(OriginalLocation, &T),
+    {
+        let resolved = self.resolved(source.len());
+        let mut insertions = resolved.iter().enumerate().peekable();
+        for (original_index, element) in source.iter().enumerate() {
+            while let Some((id, (_, inserted))) =
+                insertions.next_if(|&(_, (index, _))| index == original_index)
+            {
+                func(OriginalLocation::Insertion(id), inserted);
+            }
+            func(OriginalLocation::Original(original_index), element);
+        }
+        for (id, (_, inserted)) in insertions {
+            func(OriginalLocation::Insertion(id), inserted);
+        }
+    }
+    /// Lazily iterate over the updated locations of all the elements (both original and newly inserted).
+    ///
+    /// This yields exactly the same pairs as [Self::compute_updated_locations],
+    /// in the same order that the callback would be invoked.
+    /// That order is not sorted: it works backwards through the insertions like [Self::apply],
+    /// and ends with the original elements before the first insertion.
+    ///
+    /// Panics if any of the insertion indexes are out of bounds of `target_len`.
+    pub fn iter_updated_locations(&mut self, target_len: usize) -> UpdatedLocations<'_, T> {
+        let insertions = self.resolved(target_len);
+        let count = insertions.len();
+        UpdatedLocations {
+            insertions,
+            remaining: count,
+            original_len: target_len,
+            shifted_start: target_len + count,
+            moving: 0..0,
+            moving_updated: 0,
+            pending_insertion: None,
+        }
+    }
+    /// List the updated locations of just the elements whose final index falls within `window`,
+    /// in ascending order of their final index.
+    ///
+    /// A binary search finds where the window begins in the result,
+    /// so only the elements inside the window are ever visited.
+    /// This makes it cheap to process a huge result window by window.
+    pub fn locations_in_window(
+        &mut self,
+        target_len: usize,
+        window: Range<usize>,
+    ) -> Vec<(OriginalLocation, usize)> {
+        let resolved = self.resolved(target_len);
+        let total_len = target_len + resolved.len();
+        let end = window.end.min(total_len);
+        if window.start >= end {
+            return Vec::new();
+        }
+        /*
+         * The insertion with sorted id `k` always ends up at `index + k`,
+         * which is strictly increasing, so we can search for the first one in the window.
+         * Everything before the window that isn't an insertion must be an original element.
+         */
+        let (mut low, mut high) = (0, resolved.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if resolved.index(mid) + mid < window.start {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let mut insertion_id = low;
+        let mut original_index = window.start - insertion_id;
+        let mut result = Vec::with_capacity(end - window.start);
+        for updated in window.start..end {
+            let location = match resolved.get(insertion_id) {
+                Some((index, _)) if index <= original_index => {
+                    insertion_id += 1;
+                    OriginalLocation::Insertion(insertion_id - 1)
+                }
+                _ => {
+                    original_index += 1;
+                    OriginalLocation::Original(original_index - 1)
+                }
+            };
+            result.push((location, updated));
+        }
+        result
+    }
+    /// Iterate over the result of applying these insertions to the target,
+    /// without actually moving any memory.
+    ///
+    /// This yields the original elements and the inserted ones interleaved in their final order,
+    /// with multiple insertions at the same index in the order queued.
+    /// Any anchored insertions and padding are included without being resolved permanently,
+    /// so the set can still be applied to a target of a different length afterwards.
+    ///
+    /// Panics if any of the insertion indices are out of bounds of the target.
+    pub fn iter_applied<'a>(&'a mut self, target: &'a [T]) -> AppliedIter<'a, T> {
+        AppliedIter {
+            target: target.iter(),
+            insertions: self.resolved(target.len()),
+            next_insertion: 0,
+            next_original: 0,
+        }
+    }
+    /// Consume both the set and the target, yielding the owned elements in their final order.
+    ///
+    /// This is like [Self::iter_applied], except it moves the elements out,
+    /// so the result can be piped straight into another collection without materializing it first.
+    /// Dropping the iterator early drops all the elements that haven't been yielded yet.
+    ///
+    /// Panics if any of the insertion indices are out of bounds of the target.
+    pub fn drain_applied(mut self, target: Vec<T>) -> DrainApplied<T> {
+        self.prepare(target.len());
+        DrainApplied {
+            target: target.into_iter(),
+            insertions: mem::take(&mut self.insertions).into_iter().peekable(),
+            next_original: 0,
+        }
+    }
+    /// Apply at most `max` of the pending insertions (those with the smallest indices),
+    /// leaving the rest queued for a future apply.
+    ///
+    /// The indices of the remaining insertions are rebased to account for the elements just inserted,
+    /// so they remain valid against the (now longer) target vector.
+    pub fn apply_limited(&mut self, target: &mut Vec<T>, max: usize) {
+        self.prepare(target.len());
+        let applied = max.min(self.insertions.len());
+        if !self.seqs.is_empty() {
+            self.seqs.drain(..applied);
+        }
+        apply_exact_insertions(target, self.insertions.drain(..applied).rev());
+        /*
+         * Every remaining insertion has an index greater than or equal to the applied ones,
+         * so all of the applied elements end up before it.
+         */
+        for insertion in &mut self.insertions {
+            insertion.index += applied;
+        }
+    }
+    /// Applies all the insertions, while removing consecutive elements of the result
+    /// that are the same as their predecessor.
+    ///
+    /// The callback is invoked as `same(predecessor, element)`,
+    /// and the first element of each run of duplicates is kept (like [`Vec::dedup_by`]).
+    /// This applies to the entire result, not just the newly inserted elements.
+    ///
+    /// Duplicates are dropped as the result is compacted in place (using a [`BulkDeleter`]),
+    /// in a single walk over the original elements.
+    /// The target's existing allocation is reused,
+    /// and it only grows if the insertions outpace the duplicates removed before them.
+    pub fn apply_then_dedup<F>(&mut self, target: &mut Vec<T>, mut same: F)
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        let len = target.len();
+        self.prepare(len);
+        self.seqs.clear();
+        let mut remaining = self.insertions.len();
+        let mut insertions = self.insertions.drain(..).peekable();
+        let mut duplicate =
+            |kept: &[T], element: &T| kept.last().is_some_and(|last| same(last, element));
+        let mut deleter = BulkDeleter::new(target);
+        for original_index in 0..=len {
+            while let Some(insertion) =
+                insertions.next_if(|insertion| insertion.index == original_index)
+            {
+                remaining -= 1;
+                if duplicate(deleter.kept(), &insertion.element) {
+                    continue;
+                }
+                if deleter.room() == 0 {
+                    deleter.make_room(remaining + 1);
+                }
+                deleter.push(insertion.element);
+            }
+            let Some(element) = deleter.next_original() else {
+                break;
+            };
+            if duplicate(deleter.kept(), element) {
+                deleter.delete_next();
+            } else {
+                deleter.keep_next();
+            }
+        }
+        deleter.finish();
+    }
+    /// Applies all the insertions, while simultaneously moving the original elements
+    /// matching the predicate out of the target and into `extracted`.
+    ///
+    /// The predicate is only invoked on original elements, never on inserted ones.
+    /// Extracted elements are appended to `extracted` in their original order.
+    /// Insertions are positioned relative to the remaining original elements,
+    /// so insertions at the index of an extracted element end up where it would have been
+    /// (before the next remaining original).
+    ///
+    /// This is done in a single walk over the original elements,
+    /// compacting the result in place (using a [`BulkDeleter`]).
+    /// The target's existing allocation is reused,
+    /// and it only grows if the insertions outpace the elements extracted before them.
+    pub fn apply_partitioning<F>(
+        &mut self,
+        target: &mut Vec<T>,
+        extracted: &mut Vec<T>,
+        mut predicate: F,
+    ) where
+        F: FnMut(&T) -> bool,
+    {
+        let len = target.len();
+        self.prepare(len);
+        self.seqs.clear();
+        let mut remaining = self.insertions.len();
+        let mut insertions = self.insertions.drain(..).peekable();
+        let mut deleter = BulkDeleter::new(target);
+        for original_index in 0..=len {
+            while let Some(insertion) =
+                insertions.next_if(|insertion| insertion.index == original_index)
+            {
+                if deleter.room() == 0 {
+                    deleter.make_room(remaining);
+                }
+                remaining -= 1;
+                deleter.push(insertion.element);
+            }
+            let Some(element) = deleter.next_original() else {
+                break;
+            };
+            if predicate(element) {
+                extracted.push(deleter.take_next());
+            } else {
+                deleter.keep_next();
+            }
+        }
+        deleter.finish();
+    }
+    /// Apply the insertions against a vector with `None` holes,
+    /// filling existing holes instead of shifting memory whenever possible.
+    ///
+    /// The insertions are processed in sorted order.
+    /// Each insertion fills the first remaining `None` at or after its index,
+    /// so its final position may be later than the index it was queued at.
+    /// If no hole remains at or after the index,
+    /// the element is inserted by shifting like a regular [`Self::apply`].
+    /// In that case it is inserted at its index, or just after the last filled hole if that is later,
+    /// so that insertions still appear in sorted (and queued) order.
+    ///
+    /// Holes that are not needed are left untouched.
+    pub fn apply_filling_holes(&mut self, target: &mut Vec<Option<T>>) {
+        self.prepare(target.len());
+        let mut cursor = 0;
+        let mut after_last_hole = 0;
+        let mut shifted = Vec::new();
+        self.seqs.clear();
+        for Insertion { index, element } in self.insertions.drain(..) {
+            cursor = cursor.max(index);
+            while cursor < target.len() && target[cursor].is_some() {
+                cursor += 1;
+            }
+            if cursor < target.len() {
+                target[cursor] = Some(element);
+                cursor += 1;
+                after_last_hole = cursor;
+            } else {
+                shifted.push(Insertion::new(index.max(after_last_hole), Some(element)));
+            }
+        }
+        apply_exact_insertions(target, shifted.into_iter().rev());
+    }
+    /// Applies clones of all the insertions, leaving the queued insertions untouched.
+    ///
+    /// This makes it possible to replay the same structural edits against several vectors
+    /// with the same original shape (like a set of parallel arrays).
+    /// Every replay has exactly the same result as a normal [Self::apply],
+    /// including the order of insertions at the same index,
+    /// and any anchored insertions or padding.
+    ///
+    /// Panics if any of the insertion indices are out of bounds.
+    pub fn apply_cloned(&self, target: &mut Vec<T>)
+    where
+        T: Clone,
+    {
+        self.clone().apply(target)
+    }
+    /// Applies all the insertions, without consuming any of the queued insertions.
+    ///
+    /// The elements are cloned into the caller-provided `scratch` buffer,
+    /// which is sorted in place and then drained into the target.
+    /// This leaves the queued insertions intact so they can be applied to another vector,
+    /// while reusing the scratch buffer's allocation across calls.
+    ///
+    /// Once the scratch buffer is big enough, this only allocates to grow the target
+    /// (or when sorting more than [`HybridSortStrategy`]'s threshold of unsorted insertions,
+    /// just like [Self::apply]).
+    pub fn apply_with_scratch(&self, target: &mut Vec<T>, scratch: &mut Vec<Insertion<T>>)
+    where
+        T: Clone,
+    {
+        let len = target.len();
+        if let Err(error) = self.validate(len) {
+            panic!("{}", error);
+        }
+        scratch.clear();
+        scratch.reserve(self.required_room(len));
+        if self.seqs.is_empty() {
+            // Mirror `resolve_pending`, which queues the anchors and then the padding
+            self.for_each_queued(|index, _, element| {
+                scratch.push(Insertion::new(index, element.clone()))
+            });
+            scratch.extend(
+                self.anchored
+                    .iter()
+                    .map(|anchored| Insertion::new(len - anchored.index, anchored.element.clone())),
+            );
+            if let Some(ref padding) = self.padding {
+                let count = self.padding_count(len);
+                scratch.extend((0..count).map(|_| Insertion::new(len, padding.fill.clone())));
+            }
+            // The insertions are often queued in order, so it's worth checking first
+            if !scratch
+                .windows(2)
+                .all(|pair| pair[0].index <= pair[1].index)
+            {
+                HybridSortStrategy::default().sort_by_key(scratch, |insertion| insertion.index);
+            }
+        } else {
+            /*
+             * The scratch buffer has no room for the sequence numbers,
+             * so it temporarily holds the queued id of each insertion instead of its index.
+             * Sorting by the resolved `(index, seq)` of each id keeps ties in the order queued.
+             */
+            scratch.extend(
+                (0..self.required_room(len))
+                    .map(|id| Insertion::new(id, self.resolved_at(id, len).2.clone())),
+            );
+            HybridSortStrategy::default().sort_by_key(scratch, |insertion| {
+                let (index, seq, _) = self.resolved_at(insertion.index, len);
+                (index, seq)
+            });
+            for insertion in scratch.iter_mut() {
+                insertion.index = self.resolved_at(insertion.index, len).0;
+            }
+        }
+        apply_exact_insertions(target, DrainReverse::new(scratch));
+    }
+    /// Applies all the insertions to each of the targets.
+    ///
+    /// The insertions are only sorted once, up front.
+    /// Each target except the last one then receives clones placed straight from the sorted insertions,
+    /// so every inserted element is cloned exactly once per target (without any scratch buffer).
+    /// The last target receives the queued elements themselves.
+    /// Copies queued with [`Self::insert_many_same`] are expanded just once (for the last target),
+    /// rather than once for every target.
+    /// The result for each target is the same as a normal [`Self::apply`].
+    pub fn apply_to_many<'a, I>(mut self, targets: I)
+    where
+        I: IntoIterator<Item = &'a mut Vec<T>>,
+        T: Clone + 'a,
+    {
+        let mut targets = targets.into_iter();
+        let Some(mut target) = targets.next() else {
+            self.discard();
+            return;
+        };
+        for next in targets {
+            // Already sorted after the first target, so this only needs to validate
+            let resolved = self.resolved(target.len());
+            let placements = (0..resolved.len()).rev().map(|id| {
+                let (index, element) = resolved.get(id).unwrap();
+                Insertion::new(index, element.clone())
+            });
+            apply_exact_insertions(target, placements);
+            target = next;
+        }
+        self.apply(target);
+    }
+    /// Resolve everything queued against a target of the specified length,
+    /// returning the `(index, element)` pairs in exactly the order they would be applied.
+    ///
+    /// Unlike [Self::resolved], this never modifies the set (at the cost of allocating).
+    /// Assumes the set was already validated against the target.
+    fn resolved_refs(&self, target_len: usize) -> Vec<(usize, &T)> {
+        let padding = self.padding_count(target_len);
+        let mut resolved = Vec::with_capacity(self.desired_insertions() + padding);
+        self.for_each_queued(|index, seq, element| resolved.push((index, seq, element)));
+        // Mirror `resolve_pending`, which queues the anchors and then the padding
+        resolved.extend(
+            self.anchored
+                .iter()
+                .map(|anchored| (target_len - anchored.index, 0, &anchored.element)),
+        );
+        if let Some(ref fill) = self.padding {
+            resolved.extend((0..padding).map(|_| (target_len, u64::MAX, &fill.fill)));
+        }
+        resolved.sort_by_key(|&(index, seq, _)| (index, seq));
+        resolved
+            .into_iter()
+            .map(|(index, _, element)| (index, element))
+            .collect()
+    }
+    /// Take the individually queued insertions, discarding any anchored insertions and padding.
+    ///
+    /// This is for consuming the set without a target, so nothing can depend on its length.
+    fn take_queued(&mut self) -> Vec<Insertion<T>> {
+        self.anchored.clear();
+        self.padding = None;
+        self.seqs.clear();
+        mem::take(&mut self.insertions)
+    }
+    /// Apply at most `max` of the pending insertions like [Self::apply_limited],
+    /// but hand back the remaining insertions instead of leaving them queued.
+    ///
+    /// The remaining insertions are sorted, and their indices are rebased
+    /// so they are valid against the (now longer) target vector.
+    pub fn apply_returning_unused(&mut self, target: &mut Vec<T>, max: usize) -> Vec<Insertion<T>> {
+        self.apply_limited(target, max);
+        self.seqs.clear();
+        mem::take(&mut self.insertions)
+    }
+}
+impl<T, #[cfg(feature = "allocator_api")] A: Allocator> alloc_in!(InsertionSet<T> in A) {
+    /// Queue the specified insertion
+    ///
+    /// If there are multiple insertions at the same index,
+    /// they will be applied in the order queued.
+    #[inline]
+    pub fn push(&mut self, insertion: Insertion<T>) {
+        if !self.seqs.is_empty() {
+            // Implicitly use the lowest sequence number
+            self.seqs.push(0);
+        }
+        self.sorted = false;
+        self.insertions.push(insertion)
+    }
+    /// Queue the specified insertion, which must be at or after every insertion already queued
+    ///
+    /// As long as every insertion is queued this way (in non-decreasing index order),
+    /// the set never needs sorting, so applying it is guaranteed to be linear.
+    /// Insertions at the same index are still applied in the order queued.
+    ///
+    /// Breaking this contract panics in debug builds.
+    /// In release builds, the set just falls back to sorting like normal.
+    #[inline]
+    pub fn push_sorted(&mut self, insertion: Insertion<T>) {
+        let in_order = self
+            .insertions
+            .last()
+            .is_none_or(|last| last.index <= insertion.index);
+        debug_assert!(
+            in_order,
+            "Insertion index {} is before the last index {}",
+            insertion.index,
+            self.insertions.last().unwrap().index
+        );
+        // Sequence numbers could still reorder insertions at the same index
+        let still_sorted = self.sorted && in_order && self.seqs.is_empty();
+        self.push(insertion);
+        self.sorted = still_sorted;
+    }
+    /// Insert the element to be inserted before the given index
+    ///
+    /// If multiple elements are queued to be inserted at the same index,
+    /// they will be applied in the original order queued.
+    #[inline]
+    pub fn insert(&mut self, index: usize, element: T) {
+        self.push(Insertion { index, element })
+    }
+    /// Queue an insertion with an explicit sequence number, which controls the order of ties.
+    ///
+    /// Insertions are sorted by `(index, seq)`, so insertions at the same index
+    /// are applied in order of their sequence numbers rather than the order they were queued.
+    /// This makes the result deterministic even when insertions are gathered from multiple threads
+    /// and merged in an arbitrary order.
+    /// Insertions queued any other way implicitly have a sequence number of zero,
+    /// and ties between equal sequence numbers still fall back to the order queued.
+    pub fn insert_seq(&mut self, index: usize, seq: u64, element: T) {
+        if self.seqs.is_empty() {
+            self.expand_shared();
+            self.seqs.resize(self.insertions.len(), 0);
+        }
+        self.sorted = false;
+        self.insertions.push(Insertion::new(index, element));
+        self.seqs.push(seq);
+    }
+    /// Pad the result to the specified length by appending copies of `fill`.
+    ///
+    /// The amount of padding depends on the length of the target, so it's computed at apply time,
+    /// after accounting for all the other queued insertions.
+    /// The padding is appended after everything else (including other insertions at the end).
+    /// If the result would already be at least `target_len_after` elements, no padding is added.
+    /// Calling this again replaces the previous padding.
+    ///
+    /// Since it depends on the target, padding is only resolved once the set is applied
+    /// (or its locations are computed against a target), and isn't counted by [Self::desired_insertions].
+    pub fn pad_to(&mut self, target_len_after: usize, fill: T)
+    where
+        T: Clone,
+    {
+        self.padding = Some(Padding {
+            len: target_len_after,
+            fill,
+            clone: T::clone,
+        });
+    }
+    /// Queue an insertion at a position that may be relative to the end of the target.
+    ///
+    /// An insertion [`InsertionAnchor::FromEnd`]`(k)` is resolved at apply time
+    /// to the absolute index `target.len() - k`,
+    /// so it can be queued before the length of the target is known.
+    /// Anchored insertions are applied after any absolute insertions at the same (resolved) index,
+    /// and in the order queued relative to each other.
+    ///
+    /// An offset greater than the length of the target is out of bounds,
+    /// just like an absolute index would be (and is reported the same way).
+    /// Like padding, anchored insertions are only resolved once the set is applied
+    /// (or its locations are computed against a target).
+    pub fn insert_anchored(&mut self, anchor: InsertionAnchor, element: T) {
+        match anchor {
+            InsertionAnchor::FromStart(index) => self.insert(index, element),
+            InsertionAnchor::FromEnd(offset) => self.anchored.push(Insertion::new(offset, element)),
+        }
+    }
+    /// Resolve everything that depends on the length of the target,
+    /// queueing the anchored insertions and then any padding.
+    fn resolve_pending(&mut self, target_len: usize) {
+        for Insertion { index, element } in mem::take(&mut self.anchored) {
+            let index = target_len.checked_sub(index).unwrap_or_else(|| {
+                panic!(
+                    "{}",
+                    InsertionError {
+                        index,
+                        len: target_len
+                    }
+                )
+            });
+            self.insert(index, element);
+        }
+        let count = self.padding_count(target_len);
+        if let Some(Padding { fill, clone, .. }) = self.padding.take() {
+            if count == 0 {
+                return;
+            }
+            self.insertions.reserve(count);
+            // Padding always comes last, even after explicitly sequenced insertions
+            let seq = if self.seqs.is_empty() {
+                None
+            } else {
+                Some(u64::MAX)
+            };
+            let pad = |set: &mut Self, element| match seq {
+                Some(seq) => set.insert_seq(target_len, seq, element),
+                None => set.insert(target_len, element),
+            };
+            for _ in 1..count {
+                pad(self, clone(&fill));
+            }
+            pad(self, fill);
+        }
+    }
+    /// The number of padding elements that applying to a target of the specified length would add
+    fn padding_count(&self, target_len: usize) -> usize {
+        match self.padding {
+            Some(ref padding) => padding
+                .len
+                .saturating_sub(target_len + self.desired_insertions()),
+            None => 0,
+        }
+    }
+    /// Prepare to apply the set to a target of the specified length,
+    /// validating all the indices before resolving the anchored insertions and padding
+    /// and sorting everything.
+    ///
+    /// Every apply method starts with this, so nothing that was queued is ever skipped.
+    /// On error, nothing has been resolved yet.
+    fn try_prepare(&mut self, target_len: usize) -> Result<(), InsertionError> {
+        self.validate(target_len)?;
+        self.resolve_pending(target_len);
+        self.sort();
+        Ok(())
+    }
+    /// Prepare to apply the set like [Self::try_prepare],
+    /// panicking if any of the indices are out of bounds.
+    fn prepare(&mut self, target_len: usize) {
+        if let Err(error) = self.try_prepare(target_len) {
+            panic!("{}", error);
+        }
+    }
+    /// Apply all of the pending insertions against the specified vector,
+    /// returning the result
+    #[inline]
+    pub fn applied<#[cfg(feature = "allocator_api")] B: Allocator>(
+        mut self,
+        mut target: alloc_in!(Vec<T> in B),
+    ) -> alloc_in!(Vec<T> in B) {
+        self.apply(&mut target);
+        target
+    }
+    /// Remove all the queued insertions (and any padding),
+    /// keeping the allocated memory for reuse.
+    ///
+    /// This only affects the set itself, never any target vector.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.insertions.clear();
+        self.shared.clear();
+        self.padding = None;
+        self.anchored.clear();
+        self.seqs.clear();
+        self.sorted = true;
+    }
+    /// Remove the queued insertions that don't satisfy the predicate,
+    /// preserving the queued order of the ones that are kept.
+    ///
+    /// This mirrors [`Vec::retain`], visiting each insertion exactly once in the order queued.
+    /// Anchored insertions and padding depend on the target, so they're never removed.
+    pub fn retain<F: FnMut(&Insertion<T>) -> bool>(&mut self, mut func: F) {
+        self.expand_shared();
+        if self.seqs.is_empty() {
+            self.insertions.retain(func);
+        } else {
+            // Keep the sequence numbers parallel to the insertions
+            let mut kept = Vec::with_capacity(self.insertions.len());
+            self.insertions.retain(|insertion| {
+                let keep = func(insertion);
+                kept.push(keep);
+                keep
+            });
+            let mut kept = kept.into_iter();
+            self.seqs.retain(|_| kept.next().unwrap());
+        }
+    }
+    /// Intentionally drop the set without applying it
+    ///
+    /// This is the same as dropping it, except it never trips the `drop_check` feature.
+    #[inline]
+    pub fn discard(mut self) {
+        self.clear();
+    }
+    /// Reserve room for at least `additional` more insertions
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.insertions.reserve(additional);
+    }
+    /// The number of insertions that can be queued without reallocating
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.insertions.capacity()
+    }
+    /// Shrink the memory used by the queued insertions as much as possible
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.insertions.shrink_to_fit();
+        self.shared.shrink_to_fit();
+        self.anchored.shrink_to_fit();
+        self.seqs.shrink_to_fit();
+    }
+    /// The individually queued insertions, in their current order.
+    ///
+    /// This is the order queued, unless something has already sorted the set.
+    /// Insertions queued by [Self::insert_many_same] aren't included until they're expanded
+    /// by sorting, and neither are anchored insertions or padding (which need the target).
+    #[inline]
+    pub fn insertions(&self) -> &[Insertion<T>] {
+        &self.insertions
+    }
+    /// Iterate over all the queued insertions, in their current order,
+    /// yielding an `Insertion<&T>` that borrows each element.
+    ///
+    /// This is the same order that consuming the set with [`IntoIterator`] yields them,
+    /// so insertions queued by [Self::insert_many_same] are included in their queued position.
+    /// Those all share a single element and have no `Insertion<T>` of their own,
+    /// which is why this can't yield `&Insertion<T>` like a slice iterator.
+    /// Use [Self::insertions] to borrow just the individually queued insertions.
+    /// Anchored insertions and padding depend on the target, so they aren't included.
+    #[inline]
+    pub fn iter_queued(&self) -> QueuedIter<'_, T> {
+        QueuedIter {
+            insertions: self.insertions.iter(),
+            consumed: 0,
+            shared: self.shared.iter(),
+            shared_indices: [].iter(),
+            shared_element: None,
+        }
+    }
+    /// The number of insertions that are currently queued
+    #[inline]
+    pub fn desired_insertions(&self) -> usize {
+        self.insertions.len()
+            + self.anchored.len()
+            + self
+                .shared
+                .iter()
+                .map(|shared| shared.indices.len())
+                .sum::<usize>()
+    }
+    /// Check if applying this set would be a no-op,
+    /// because there are no queued insertions (or padding).
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.desired_insertions() == 0 && self.padding.is_none()
+    }
+    /// Applies all the insertions to the specified target vector.
+    ///
+    /// This reuses the Vector's existing memory if possible,
+    /// but may require a reallocation (due to new values)
+    ///
+    /// The average runtime of this function is `O(n + m)`,
+    /// where `n` is the number of existing elements and `m` is the number of insertions.
+    /// The worst case running time is `O((k * log(k))` where `k = n + m`.
+    ///
+    /// Panics if any of the insertion indices are out of bounds.
+    /// See [Self::try_apply] for a fallible version.
+    pub fn apply<#[cfg(feature = "allocator_api")] B: Allocator>(
+        &mut self,
+        target: &mut alloc_in!(Vec<T> in B),
+    ) {
+        self.try_apply(target)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+    /// Applies all the insertions to the specified target vector,
+    /// unless any of the insertion indices are out of bounds.
+    ///
+    /// All the indices are validated up front, so on error the target is left completely untouched
+    /// (including its capacity) and the insertions remain queued.
+    /// The error describes the first out-of-bounds insertion.
+    pub fn try_apply<#[cfg(feature = "allocator_api")] B: Allocator>(
+        &mut self,
+        target: &mut alloc_in!(Vec<T> in B),
+    ) -> Result<(), InsertionError> {
+        if self.is_empty() {
+            // Don't even touch the target
+            return Ok(());
+        }
+        self.try_prepare(target.len())?;
+        apply_exact_insertions(target, self.drain_sorted());
+        Ok(())
+    }
+    /// Apply all of the pending insertions against the specified vector,
+    /// returning the result unless any of the insertion indices are out of bounds.
+    #[inline]
+    pub fn try_applied<#[cfg(feature = "allocator_api")] B: Allocator>(
+        mut self,
+        mut target: alloc_in!(Vec<T> in B),
+    ) -> Result<alloc_in!(Vec<T> in B), InsertionError> {
+        self.try_apply(&mut target)?;
+        Ok(target)
+    }
+    /// Invoke the callback on each queued insertion (including shared ones),
+    /// in the order they were queued.
+    ///
+    /// The callback also receives the sequence number of the insertion,
+    /// which is zero unless it was explicitly sequenced with [Self::insert_seq].
+    fn for_each_queued<'a, F>(&'a self, mut func: F)
+    where
+        F: FnMut(usize, u64, &'a T),
+    {
+        let seq = |id: usize| self.seqs.get(id).copied().unwrap_or(0);
+        let mut consumed = 0;
+        for shared in &self.shared {
+            for id in consumed..shared.position {
+                let insertion = &self.insertions[id];
+                func(insertion.index, seq(id), &insertion.element);
+            }
+            consumed = shared.position;
+            for &index in &shared.indices {
+                func(index, 0, &shared.element);
+            }
+        }
+        for (id, insertion) in self.insertions.iter().enumerate().skip(consumed) {
+            func(insertion.index, seq(id), &insertion.element);
+        }
+    }
+    /// Resolve the insertion with the specified id against a target of the specified length,
+    /// returning its `(index, seq, element)`.
+    ///
+    /// The ids count the insertions in the order [Self::resolved_refs] collects them:
+    /// everything queued (including shared insertions), then the anchored insertions,
+    /// and finally the padding.
+    /// This takes time proportional to the number of shared groups.
+    fn resolved_at(&self, id: usize, target_len: usize) -> (usize, u64, &T) {
+        let seq = |id: usize| self.seqs.get(id).copied().unwrap_or(0);
+        // The number of shared insertions before `id`
+        let mut skipped = 0;
+        for shared in &self.shared {
+            let start = shared.position + skipped;
+            if id < start {
+                break;
+            }
+            if let Some(&index) = shared.indices.get(id - start) {
+                return (index, 0, &shared.element);
+            }
+            skipped += shared.indices.len();
+        }
+        let individual = id - skipped;
+        if let Some(insertion) = self.insertions.get(individual) {
+            return (insertion.index, seq(individual), &insertion.element);
+        }
+        match self.anchored.get(individual - self.insertions.len()) {
+            Some(anchored) => (target_len - anchored.index, 0, &anchored.element),
+            None => {
+                let padding = self.padding.as_ref().expect("Invalid insertion id");
+                (target_len, u64::MAX, &padding.fill)
+            }
+        }
+    }
+    /// Check that all the queued insertions are in bounds for a target of the specified length
+    fn validate(&self, target_len: usize) -> Result<(), InsertionError> {
+        self.validate_split(target_len, target_len)
+    }
+    /// Check that all the queued insertions are in bounds for a target of length `index_len`,
+    /// and that the anchored insertions are in bounds once it has length `anchored_len`.
+    ///
+    /// The lengths only differ when something else changes the target first (like deletions).
+    fn validate_split(&self, index_len: usize, anchored_len: usize) -> Result<(), InsertionError> {
+        let mut first_invalid = None;
+        self.for_each_queued(|index, _, _| {
+            if index > index_len && first_invalid.is_none() {
+                first_invalid = Some(index);
+            }
+        });
+        if let Some(index) = first_invalid {
+            return Err(InsertionError {
+                index,
+                len: index_len,
+            });
+        }
+        // The offset of an anchored insertion has exactly the same bound as an index
+        match self
+            .anchored
+            .iter()
+            .map(|insertion| insertion.index)
+            .find(|&offset| offset > anchored_len)
+        {
+            Some(offset) => Err(InsertionError {
+                index: offset,
+                len: anchored_len,
+            }),
+            None => Ok(()),
+        }
+    }
+    /// Sort the insertions, then drain them in reverse order (as `apply_bulk_insertions` expects)
+    fn drain_sorted(&mut self) -> alloc_in!(DrainReverse<'_, Insertion<T>> in A) {
+        self.sort();
+        self.seqs.clear();
+        DrainReverse::new(&mut self.insertions)
+    }
+    /// Expand all the shared insertions into individual ones,
+    /// cloning their elements as needed.
+    fn expand_shared(&mut self) {
+        if self.shared.is_empty() {
+            return;
+        }
+        let mut expanded = Vec::with_capacity(self.desired_insertions());
+        let mut individual = self.insertions.drain(..).collect::<Vec<_>>().into_iter();
+        let has_seqs = !self.seqs.is_empty();
+        let mut expanded_seqs = Vec::new();
+        let mut individual_seqs = mem::take(&mut self.seqs).into_iter();
+        let mut consumed = 0;
+        for shared in mem::take(&mut self.shared) {
+            expanded.extend(individual.by_ref().take(shared.position - consumed));
+            if has_seqs {
+                expanded_seqs.extend(individual_seqs.by_ref().take(shared.position - consumed));
+                expanded_seqs.resize(expanded_seqs.len() + shared.indices.len(), 0);
+            }
+            consumed = shared.position;
+            let SharedInsertions {
+                indices,
+                element,
+                clone,
+                ..
+            } = shared;
+            let (&last, rest) = indices.split_last().unwrap();
+            for &index in rest {
+                expanded.push(Insertion::new(index, clone(&element)));
+            }
+            // Move the original into the final position, saving a clone
+            expanded.push(Insertion::new(last, element));
+        }
+        expanded.extend(individual);
+        expanded_seqs.extend(individual_seqs);
+        self.insertions.extend(expanded);
+        self.seqs = expanded_seqs;
+        self.sorted = false;
+    }
+    /// Sort the queued insertions now, using the specified strategy.
+    ///
+    /// Applying the set always sorts with the default [`HybridSortStrategy`],
+    /// but that's cheap once the insertions are already sorted.
+    /// So sorting with a different strategy first (like [`InsertionSortStrategy`]
+    /// for input that is known to be mostly sorted, or a hybrid with a different threshold)
+    /// effectively overrides the default.
+    pub fn sort_with<S: SortStrategy>(&mut self, strategy: S) {
+        self.sort_using(
+            |insertions| strategy.sort_by_key(insertions, |insertion| insertion.index),
+            |sequenced| strategy.sort_by_key(sequenced, |(seq, insertion)| (insertion.index, *seq)),
+        )
+    }
+    /// Sort the queued insertions now, using a parallel sort if there are at least `threshold`.
+    ///
+    /// Only the sort is parallel, since shifting the target is inherently sequential.
+    /// Like [Self::sort_with], a later apply won't need to sort again.
+    /// See [`PARALLEL_SORT_THRESHOLD`] for a reasonable default threshold.
+    ///
+    /// This requires the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_sort(&mut self, threshold: usize)
+    where
+        T: Send,
+    {
+        use rayon::slice::ParallelSliceMut;
+        if self.desired_insertions() < threshold {
+            self.sort();
+            return;
+        }
+        self.sort_using(
+            |insertions| insertions.par_sort_by_key(|insertion| insertion.index),
+            |sequenced| sequenced.par_sort_by_key(|(seq, insertion)| (insertion.index, *seq)),
+        )
+    }
+    /// Expand the shared insertions and sort everything (unless it's already sorted)
+    /// with the specified stable sorts.
+    fn sort_using<F, G>(&mut self, sort_insertions: F, sort_sequenced: G)
+    where
+        F: FnOnce(&mut [Insertion<T>]),
+        G: FnOnce(&mut [(u64, Insertion<T>)]),
+    {
+        self.expand_shared();
+        if self.sorted || self.insertions.len() <= 1 {
+            // Trivially sorted, which is surprisingly common in incremental use
+            self.sorted = true;
+            return;
+        }
+        /*
+         * Insertions are often queued while walking the target front-to-back,
+         * so it's worth a linear scan to avoid sorting entirely.
+         */
+        let already_sorted = if self.seqs.is_empty() {
+            self.insertions
+                .windows(2)
+                .all(|pair| pair[0].index <= pair[1].index)
+        } else {
+            self.insertions
+                .windows(2)
+                .zip(self.seqs.windows(2))
+                .all(|(pair, seqs)| (pair[0].index, seqs[0]) <= (pair[1].index, seqs[1]))
+        };
+        self.sorted = true;
+        if already_sorted {
+            return;
+        }
+        if self.seqs.is_empty() {
+            sort_insertions(&mut self.insertions);
+        } else {
+            let mut sequenced = mem::take(&mut self.seqs)
+                .into_iter()
+                .zip(self.insertions.drain(..))
+                .collect::<Vec<_>>();
+            sort_sequenced(&mut sequenced);
+            self.seqs.reserve(sequenced.len());
+            for (seq, insertion) in sequenced {
+                self.seqs.push(seq);
+                self.insertions.push(insertion);
+            }
+        }
+    }
+    fn sort(&mut self) {
+        /*
+         * In many scenarios, the input is mostly sorted.
+         * In those cases, insertion sort may be better than std::slice::sort.
+         * When the array is already mostly sorted, insertion sort has average running time `O(nk)`,
+         * where `k` is the average distance of each element from its proper position.
+         *
+         * Previous versions of this code used insertion sort for this reason,
+         * because we expected the input to be mostly sorted in the compiler.
+         * However, now that we are a library, we want to support as many use cases as possible.
+         * The documentation guarantees linear behavior,
+         * and we don't want to have quadratic blowup if the user has unexpected input.
+         * See issue #1 for details.
+         *
+         * The big advantage of insertion sort over std::slice::sort is that it avoids allocation.
+         * So we still use insertion sort for small sets, where even the worst case is cheap,
+         * and fall back to stdlib sort for anything larger.
+         * The user can tune the threshold (or pick another strategy) by calling `sort_with` first.
+         */
+        self.sort_with(HybridSortStrategy::default())
+    }
+}
+#[cfg(feature = "allocator_api")]
+impl<T, A: Allocator> InsertionSet<T, A> {
+    /// Create a new InsertionSet that stores its insertions using the specified allocator
+    ///
+    /// Applying the set never allocates with the global allocator,
+    /// unless it uses one of the features listed in the [type documentation](InsertionSet)
+    /// or has to sort more than [`HybridSortStrategy`]'s threshold of unsorted insertions
+    /// (which uses the standard library's stable sort).
+    /// Queue insertions with [Self::push_sorted] or sort them with [`InsertionSortStrategy`]
+    /// to avoid that.
+    ///
+    /// This requires the nightly-only `allocator_api` feature.
+    #[inline]
+    pub fn new_in(alloc: A) -> Self {
+        InsertionSet {
+            insertions: Vec::new_in(alloc),
+            shared: Vec::new(),
+            padding: None,
+            anchored: Vec::new(),
+            seqs: Vec::new(),
+            sorted: true,
+        }
+    }
+    /// Create a new InsertionSet using the specified allocator,
+    /// with room for at least `capacity` insertions
+    ///
+    /// This requires the nightly-only `allocator_api` feature.
+    #[inline]
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        let mut result = InsertionSet::new_in(alloc);
+        result.insertions.reserve_exact(capacity);
+        result
+    }
+    /// The allocator used to store the queued insertions
+    ///
+    /// This requires the nightly-only `allocator_api` feature.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        self.insertions.allocator()
+    }
+}
+/// Helpers for sets of boxed elements (including trait objects like `Box<dyn Debug>`).
+///
+/// None of the methods on [`InsertionSet`] require `T: Clone`,
+/// so boxed trait objects can be queued and applied like any other element.
+impl<T: ?Sized> InsertionSet<Box<T>> {
+    /// Box the specified value, then queue it for insertion before the given index
+    ///
+    /// This accepts anything with a conversion into `Box<T>`,
+    /// like a `&str` for `Box<str>` or an error for `Box<dyn Error>`.
+    #[inline]
+    pub fn insert_boxed<U: Into<Box<T>>>(&mut self, index: usize, value: U) {
+        self.insert(index, value.into())
+    }
+}
+/// Helpers for sets that only store the indices of insertions,
+/// with the elements supplied separately at apply time.
+impl InsertionSet<()> {
+    /// Applies the queued insertions to the target,
+    /// lazily pulling each inserted element from the specified source.
+    ///
+    /// Insertions are applied in reverse order (from the back of the vector),
+    /// so the first element of the source is placed at the last insertion,
+    /// the second element at the second-to-last, and so on.
+    /// Among insertions at the same index, the last one queued counts as the "last" one.
+    ///
+    /// Panics if the source runs out of elements.
+    pub fn apply_with_element_source<T, I>(&mut self, target: &mut Vec<T>, mut elements: I)
+    where
+        I: Iterator<Item = T>,
+    {
+        self.prepare(target.len());
+        let insertions = self.drain_sorted().map(|insertion| {
+            let element = elements.next().expect("Expected more elements!");
+            Insertion::new(insertion.index, element)
+        });
+        apply_exact_insertions(target, insertions);
+    }
+}
+impl<T> FromIterator<Insertion<T>> for InsertionSet<T> {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = Insertion<T>>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut result = InsertionSet::with_capacity(iter.size_hint().0);
+        result.extend(iter);
+        result
+    }
+}
+/// Consumes the set, yielding the insertions in the order they were queued
+/// (unless something already sorted the set).
+///
+/// Insertions queued by [`InsertionSet::insert_many_same`] are expanded in their queued position.
+/// Anchored insertions and padding depend on the target, so they aren't included.
+impl<T> IntoIterator for InsertionSet<T> {
+    type Item = Insertion<T>;
+    type IntoIter = alloc::vec::IntoIter<Insertion<T>>;
+
+    #[inline]
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.expand_shared();
+        self.take_queued().into_iter()
+    }
+}
+/// Iterates over all the queued insertions exactly like [`InsertionSet::iter_queued`],
+/// yielding an `Insertion<&T>` for each (rather than `&Insertion<T>`).
+impl<'a, T> IntoIterator for &'a InsertionSet<T> {
+    type Item = Insertion<&'a T>;
+    type IntoIter = QueuedIter<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_queued()
+    }
+}
+impl<T> Extend<Insertion<T>> for InsertionSet<T> {
+    #[inline]
+    fn extend<I: IntoIterator<Item = Insertion<T>>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for insertion in iter {
+            self.push(insertion);
+        }
+    }
+}
+impl<T> Extend<(usize, T)> for InsertionSet<T> {
+    #[inline]
+    fn extend<I: IntoIterator<Item = (usize, T)>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().map(Insertion::from))
+    }
+}
+impl<T> FromIterator<(usize, T)> for InsertionSet<T> {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = (usize, T)>>(iter: I) -> Self {
+        iter.into_iter().map(Insertion::from).collect()
+    }
+}
+impl<T: Debug, #[cfg(feature = "allocator_api")] A: Allocator> Debug for alloc_in!(InsertionSet<T> in A) {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut queued = Vec::with_capacity(self.desired_insertions());
+        self.for_each_queued(|index, _, element| queued.push((index, element)));
+        f.debug_struct("InsertionSet")
+            .field("insertions", &queued)
+            .field("desired_insertions", &self.desired_insertions())
+            .finish()
+    }
+}
+/// Catches sets that are accidentally dropped without ever being applied.
+///
+/// Applying a set drains it, so only non-empty sets are ever reported.
+/// The unit tests drop plenty of sets on purpose, so they're checked separately.
+#[cfg(all(feature = "drop_check", debug_assertions, not(test)))]
+impl<T, #[cfg(feature = "allocator_api")] A: Allocator> Drop for alloc_in!(InsertionSet<T> in A) {
+    fn drop(&mut self) {
+        if self.is_empty() || std::thread::panicking() {
+            return;
+        }
+        match self.padding {
+            Some(ref padding) => panic!(
+                "InsertionSet with {} insertions and padding to len {} was dropped without being applied",
+                self.desired_insertions(),
+                padding.len
+            ),
+            None => panic!(
+                "InsertionSet with {} insertions was dropped without being applied",
+                self.desired_insertions()
+            ),
+        }
+    }
+}
+impl<T: Clone> Clone for InsertionSet<T> {
+    fn clone(&self) -> Self {
+        InsertionSet {
+            insertions: self.insertions.clone(),
+            shared: self.shared.clone(),
+            padding: self.padding.clone(),
+            anchored: self.anchored.clone(),
+            seqs: self.seqs.clone(),
+            sorted: self.sorted,
+        }
+    }
+}
+/// Two sets are equal if they queue the same insertions in the same order.
+///
+/// The order is significant since it determines the order of insertions at the same index,
+/// so sets that queued the same insertions in a different order are not equal.
+impl<T: PartialEq> PartialEq for InsertionSet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut queued = Vec::with_capacity(self.desired_insertions());
+        self.for_each_queued(|index, _, element| queued.push((index, element)));
+        let mut other_queued = Vec::with_capacity(other.desired_insertions());
+        other.for_each_queued(|index, _, element| other_queued.push((index, element)));
+        let same_padding = match (&self.padding, &other.padding) {
+            (Some(padding), Some(other_padding)) => {
+                padding.len == other_padding.len && padding.fill == other_padding.fill
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        queued == other_queued
+            && same_padding
+            && self.anchored == other.anchored
+            && self.seqs == other.seqs
+    }
+}
+impl<T: Eq> Eq for InsertionSet<T> {}
+impl<T> Default for InsertionSet<T> {
+    #[inline]
+    fn default() -> Self {
+        InsertionSet::new()
+    }
+}
+
+/// The position of an insertion queued by [`InsertionSet::insert_anchored`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum InsertionAnchor {
+    /// An absolute index, counting from the start of the target
+    FromStart(usize),
+    /// An offset counting back from the end of the target,
+    /// so `FromEnd(0)` appends to the end
+    FromEnd(usize),
+}
+
+/// A compact record of the elements placed by applying an [`InsertionSet`].
+///
+/// The original elements always keep their relative order,
+/// so the final positions of the inserted elements are enough to
+/// reconstruct the result from the original vector.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InsertionDelta<T> {
+    /// The final position of each inserted element (in ascending order),
+    /// along with the element itself
+    pub placements: Vec<(usize, T)>,
+    /// The length of the original vector
+    pub original_len: usize,
+}
+impl<T: Clone> InsertionDelta<T> {
+    /// Replay this delta against a copy of the original vector,
+    /// giving the same result as the original apply.
+    ///
+    /// Panics if the target doesn't have the original length.
+    pub fn replay(&self, target: &mut Vec<T>) {
+        assert_eq!(
+            target.len(),
+            self.original_len,
+            "Target doesn't match the original length"
+        );
+        /*
+         * Each placement is preceded by exactly `id` other placements,
+         * so its index in the original vector is its final position minus that.
+         */
+        let mut insertions = self
+            .placements
+            .iter()
+            .enumerate()
+            .map(|(id, (position, element))| Insertion::new(position - id, element.clone()))
+            .collect::<InsertionSet<T>>();
+        insertions.apply(target);
+    }
+}
+
+/// Records the elements placed by [`InsertionSet::apply_transactional`],
+/// so that they can be removed again with [`revert`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RevertToken {
+    /// The final positions of the inserted elements, in descending order
+    positions: Vec<usize>,
+}
+impl RevertToken {
+    /// The number of inserted elements that will be removed on revert
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    /// Check if reverting would be a no-op
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// Undoes a transactional apply, restoring the target to its original state
+/// and returning the removed elements (in the order they appear in the target).
+///
+/// All the elements are removed in place, in a single pass.
+/// Panics (before removing anything) if the token's positions aren't strictly descending,
+/// or if the target is too short to have come from the apply that created the token.
+pub fn revert<T>(target: &mut Vec<T>, token: RevertToken) -> Vec<T> {
+    assert!(
+        token.positions.windows(2).all(|pair| pair[0] > pair[1]),
+        "Revert positions must be strictly descending"
+    );
+    if let Some(&last) = token.positions.first() {
+        assert!(
+            last < target.len(),
+            "Invalid revert position {} >= len {}",
+            last,
+            target.len()
+        );
+    }
+    let mut removed = Vec::with_capacity(token.positions.len());
+    let mut deleter = BulkDeleter::new(target);
+    for &position in token.positions.iter().rev() {
+        deleter.take_range(position..position + 1, &mut removed);
+    }
+    deleter.finish();
+    removed
+}
+
+/// Padding requested by [`InsertionSet::pad_to`]
+#[derive(Clone)]
+struct Padding<T> {
+    /// The desired length of the result
+    len: usize,
+    fill: T,
+    /// Used to clone the fill without requiring `T: Clone` everywhere
+    clone: fn(&T) -> T,
+}
+
+/// A single element queued for insertion at many indices
+#[derive(Clone, Debug)]
+struct SharedInsertions<T> {
+    /// The number of individual insertions that were queued before this group
+    position: usize,
+    /// The indices to insert copies of the element at (never empty)
+    indices: Vec<usize>,
+    element: T,
+    /// Used to clone the element without requiring `T: Clone` everywhere
+    clone: fn(&T) -> T,
+}
+
+/// Drains the elements of a vector in reverse order,
+/// popping them off the back one at a time.
+///
+/// The vector shrinks as the iterator advances.
+/// If the iterator is dropped early, the remaining elements are left in the vector.
+///
+/// When the vector is sorted in ascending order, this gives exactly the
+/// reverse-sorted [`ExactSizeIterator`] that [`apply_bulk_insertions`] requires:
+/// ```
+/// # use insertion_set::{apply_bulk_insertions, DrainReverse, Insertion};
+/// let mut insertions = vec![Insertion::new(0, 0), Insertion::new(2, 3)];
+/// let mut target = vec![1, 2, 4];
+/// apply_bulk_insertions(&mut target, DrainReverse::new(&mut insertions));
+/// assert_eq!(target, vec![0, 1, 2, 3, 4]);
+/// assert!(insertions.is_empty());
+/// ```
+pub struct DrainReverse<'a, T: 'a, #[cfg(feature = "allocator_api")] A: Allocator = Global>(
+    &'a mut alloc_in!(Vec<T> in A),
+);
+impl<'a, T, #[cfg(feature = "allocator_api")] A: Allocator> alloc_in!(DrainReverse<'a, T> in A) {
+    /// Drain the specified vector in reverse order
+    #[inline]
+    pub fn new(target: &'a mut alloc_in!(Vec<T> in A)) -> Self {
+        DrainReverse(target)
+    }
+}
+impl<'a, T, #[cfg(feature = "allocator_api")] A: Allocator> Iterator for alloc_in!(DrainReverse<'a, T> in A) {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len(), Some(self.0.len()))
+    }
+}
+impl<'a, T, #[cfg(feature = "allocator_api")] A: Allocator> ExactSizeIterator for alloc_in!(DrainReverse<'a, T> in A) {}
+impl<'a, T: Debug, #[cfg(feature = "allocator_api")] A: Allocator> Debug for alloc_in!(DrainReverse<'a, T> in A) {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("DrainReverse").field(&self.0).finish()
+    }
+}
+
+/// Applies all the specified insertions into the target vector.
+///
+/// The insertion iterator must be sorted in reverse order and give the proper size for its `ExactSizeIterator`.
+/// Violating these constraints will never cause undefined behavior,
+/// since internally we use the completely safe `BulkShifter` abstraction.
+///
+/// A wrong length can only be detected once the iterator runs out (or doesn't),
+/// so this records where each insertion was placed along the way.
+/// If the iterator yields too few or too many insertions,
+/// everything that was placed is dropped (along with any extras)
+/// and the original elements are shifted back before panicking,
+/// leaving the target with exactly the elements it started with.
+/// Use [`apply_bulk_insertions_untrusted`] to apply the insertions anyway.
+///
+/// If anything else panics partway through (including the iterator itself),
+/// the target is left holding the original elements that haven't been moved yet,
+/// followed by everything that was already placed.
+/// No elements are ever leaked or dropped twice.
+pub fn apply_bulk_insertions<T, I, #[cfg(feature = "allocator_api")] A: Allocator>(
+    target: &mut alloc_in!(Vec<T> in A),
+    mut insertions: I,
+) where
+    I: Iterator<Item = Insertion<T>>,
+    I: ExactSizeIterator,
+{
+    let mut shifter = BulkShifter::new(target, insertions.len());
+    let mut placed = Vec::new();
+    let result = try_shift_insertions(&mut shifter, &mut insertions, |event| {
+        if let ShiftEvent::Push { index } = event {
+            placed.push(index);
+        }
+    });
+    if let Err(mismatch) = result {
+        // Each insertion is placed before the previous one, so the positions are descending
+        shifter.rollback(placed.into_iter().rev());
+        panic!("{}", mismatch);
+    }
+    shifter.finish();
+}
+
+/// Applies insertions from an iterator whose length is known to be exact
+/// (like [`DrainReverse`] or a `Vec` iterator), exactly like [`apply_bulk_insertions`].
+///
+/// This skips recording the positions that are needed to roll back a wrong length,
+/// so it never allocates anything besides growing the target.
+pub(crate) fn apply_exact_insertions<T, I, #[cfg(feature = "allocator_api")] A: Allocator>(
+    target: &mut alloc_in!(Vec<T> in A),
+    insertions: I,
+) where
+    I: Iterator<Item = Insertion<T>>,
+    I: ExactSizeIterator,
+{
+    let shifter = BulkShifter::new(target, insertions.len());
+    shift_insertions(shifter, insertions, |_| {})
+}
+
+/// Applies all the specified insertions into the target vector,
+/// without trusting the length reported by the iterator.
+///
+/// The insertions must still be sorted in reverse order,
+/// but they are collected into a buffer before any shifting is done.
+/// That gives a trustworthy length, at the cost of an extra allocation.
+/// This is the safer choice when the iterator's `size_hint` or `len` may be wrong.
+pub fn apply_bulk_insertions_untrusted<T, I, #[cfg(feature = "allocator_api")] A: Allocator>(
+    target: &mut alloc_in!(Vec<T> in A),
+    insertions: I,
+) where
+    I: IntoIterator<Item = Insertion<T>>,
+{
+    let insertions = insertions.into_iter().collect::<Vec<_>>();
+    apply_exact_insertions(target, insertions.into_iter())
+}
+
+/// A single step of memory movement performed by [`apply_bulk_insertions`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum ShiftEvent {
+    /// Shifted `count` original elements (starting at original index `start`)
+    /// to the right, so that they now start at index `to`
+    Shift {
+        start: usize,
+        count: usize,
+        to: usize,
+    },
+    /// Wrote an inserted element into the specified (final) index
+    Push { index: usize },
+}
+
+/// An `ExactSizeIterator` of insertions that didn't yield exactly `len` insertions
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum LengthMismatch {
+    /// The iterator ran out after yielding only `yielded` insertions
+    TooFew { expected: usize, yielded: usize },
+    /// The iterator still had insertions left after yielding `expected`
+    TooMany { expected: usize },
+}
+impl Display for LengthMismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            LengthMismatch::TooFew { expected, yielded } => write!(
+                f,
+                "Expected {} insertions from the ExactSizeIterator, but only got {}",
+                expected, yielded
+            ),
+            LengthMismatch::TooMany { expected } => write!(
+                f,
+                "Expected {} insertions from the ExactSizeIterator, but got more",
+                expected
+            ),
+        }
+    }
+}
+
+/// Applies the insertions exactly like [`apply_bulk_insertions`],
+/// using an existing shifter and notifying the observer of each step of memory movement.
+///
+/// The shifter must have been created with room for exactly `insertions.len()` elements.
+/// The iterator is trusted to have an exact length,
+/// so a mismatch just panics (without rolling anything back).
+pub(crate) fn shift_insertions<T, I, F, #[cfg(feature = "allocator_api")] A: Allocator>(
+    mut shifter: alloc_in!(BulkShifter<'_, T> in A),
+    mut insertions: I,
+    observer: F,
+) where
+    I: Iterator<Item = Insertion<T>>,
+    I: ExactSizeIterator,
+    F: FnMut(ShiftEvent),
+{
+    if let Err(mismatch) = try_shift_insertions(&mut shifter, &mut insertions, observer) {
+        panic!("{}", mismatch);
+    }
+    shifter.finish();
+}
+
+/// Shift the insertions into place, notifying the observer of each step of memory movement,
+/// unless the iterator turns out to have the wrong length.
+///
+/// On success the shifter is finished, but it's left to the caller to actually finish it.
+/// On error, the shifter holds everything that was placed so far (and any extras have been dropped).
+fn try_shift_insertions<T, I, F, #[cfg(feature = "allocator_api")] A: Allocator>(
+    shifter: &mut alloc_in!(BulkShifter<'_, T> in A),
+    insertions: &mut I,
+    mut observer: F,
+) -> Result<(), LengthMismatch>
+where
+    I: Iterator<Item = Insertion<T>>,
+    I: ExactSizeIterator,
+    F: FnMut(ShiftEvent),
+{
+    /*
+     * We perform insertions in reverse order to reduce moving memory,
+     * and ensure that the function is panic safe.
+     *
+     * For example, given the vector
+     * and the InsertionSet `[(0, 0), (1, 2), (1, 3) (4, 9)]`:
+     *
+     * Since the first (working backwards) insertion is `(4, 9)`,
+     * we need to to shift all elements after our first insertion
+     * to the left 4 places:
+     * `[1, 4, 5, 7, undef, undef, undef, undef, 11]`.
+     * The element `11` will never need to be moved again,
+     * since we've already made room for all future insertions.
+     *
+     * Next, we perform our first insertion (4, 9) at the last `undef` element:
+     * `[1, 4, 5, 7, undef, undef, undef, 9, 11]`.
+     * We only have 3 insertions left to perform,
+     * so all future shifts will only need to move over two.
+     * Then, we handle the group of insertions `[(1, 2), [(1, 3)]`,
+     * and shift all elements past index 1 to the left 3 spaces:
+     * [1, undef, undef, undef, 4, 5, 7, 9, 11].
+     * Then we perform our desired insertions at index 1:
+     * [1, undef, 2, 3, 4, 9, 11].
+     * Finally, we perform the same process for the final insertion (0, 0),
+     * resulting in the desired result: [0, 1, 2, 3, 4, 9, 11].
+     */
+    let expected = insertions.len();
+    let mut yielded = 0;
     while !shifter.is_finished() {
-        let Insertion { index, element } = insertions.next().expect("Expected more insertions!");
-        shifter.shift_original(index);
+        let Some(Insertion { index, element }) = insertions.next() else {
+            return Err(LengthMismatch::TooFew { expected, yielded });
+        };
+        yielded += 1;
+        let count = shifter.shift_original(index);
+        if count > 0 {
+            observer(ShiftEvent::Shift {
+                start: index,
+                count,
+                to: shifter.shifted_start(),
+            });
+        }
         shifter.push_shifted(element);
+        observer(ShiftEvent::Push {
+            index: shifter.shifted_start(),
+        });
+    }
+    // Don't trust `len` here, since it's what we're checking
+    match insertions.next() {
+        Some(_) => Err(LengthMismatch::TooMany { expected }),
+        None => Ok(()),
+    }
+}
+
+/// The insertions of a set resolved against a specific target length, in sorted order.
+///
+/// This borrows the queued insertions directly unless there's anything that depends on the target,
+/// in which case everything is resolved into a temporary list.
+#[derive(Debug)]
+enum Resolved<'a, T> {
+    /// Nothing depends on the target, so the queued insertions are already resolved
+    Queued(&'a [Insertion<T>]),
+    /// The `(index, element)` pairs, including the anchored insertions and padding
+    Pending(Vec<(usize, &'a T)>),
+}
+impl<'a, T> Resolved<'a, T> {
+    #[inline]
+    fn len(&self) -> usize {
+        match *self {
+            Resolved::Queued(insertions) => insertions.len(),
+            Resolved::Pending(ref resolved) => resolved.len(),
+        }
+    }
+    #[inline]
+    fn get(&self, id: usize) -> Option<(usize, &'a T)> {
+        match *self {
+            Resolved::Queued(insertions) => insertions
+                .get(id)
+                .map(|insertion| (insertion.index, &insertion.element)),
+            Resolved::Pending(ref resolved) => resolved.get(id).copied(),
+        }
+    }
+    /// The index of the insertion with the specified (sorted) id
+    #[inline]
+    fn index(&self, id: usize) -> usize {
+        match *self {
+            Resolved::Queued(insertions) => insertions[id].index,
+            Resolved::Pending(ref resolved) => resolved[id].0,
+        }
+    }
+    /// Count the insertions whose index is strictly less than `index`
+    #[inline]
+    fn count_before(&self, index: usize) -> usize {
+        match *self {
+            Resolved::Queued(insertions) => {
+                insertions.partition_point(|insertion| insertion.index < index)
+            }
+            Resolved::Pending(ref resolved) => resolved.partition_point(|&(i, _)| i < index),
+        }
+    }
+    /// Iterate over the `(index, element)` pairs in sorted order
+    fn iter(&self) -> impl Iterator<Item = (usize, &'a T)> + '_ {
+        (0..self.len()).map(|id| self.get(id).unwrap())
+    }
+}
+
+/// Lazily computes the updated locations of all elements (original + inserted).
+///
+/// See [InsertionSet::iter_updated_locations] for details
+#[derive(Debug)]
+pub struct UpdatedLocations<'a, T> {
+    /// The sorted insertions
+    insertions: Resolved<'a, T>,
+    /// The number of insertions that haven't been reached yet (working backwards)
+    remaining: usize,
+    /// The number of original elements that haven't been moved yet
+    original_len: usize,
+    /// The start of the elements that have already been placed
+    shifted_start: usize,
+    /// The range of original elements that are currently being moved
+    moving: Range<usize>,
+    /// The updated location of the next element in `moving`
+    moving_updated: usize,
+    /// The id of the insertion to place once `moving` is exhausted
+    pending_insertion: Option<usize>,
+}
+impl<'a, T> Iterator for UpdatedLocations<'a, T> {
+    type Item = (OriginalLocation, usize);
+
+    fn next(&mut self) -> Option<(OriginalLocation, usize)> {
+        loop {
+            if let Some(original_index) = self.moving.next() {
+                let updated = self.moving_updated;
+                self.moving_updated += 1;
+                return Some((OriginalLocation::Original(original_index), updated));
+            }
+            if let Some(insertion_id) = self.pending_insertion.take() {
+                self.shifted_start -= 1;
+                return Some((
+                    OriginalLocation::Insertion(insertion_id),
+                    self.shifted_start,
+                ));
+            }
+            if self.remaining > 0 {
+                self.remaining -= 1;
+                let insertion_index = self.insertions.index(self.remaining);
+                let moved_memory = self.original_len - insertion_index;
+                self.shifted_start -= moved_memory;
+                self.moving = insertion_index..self.original_len;
+                self.moving_updated = self.shifted_start;
+                self.original_len = insertion_index;
+                self.pending_insertion = Some(self.remaining);
+            } else if self.original_len > 0 {
+                // The leading elements are never moved
+                self.moving = 0..self.original_len;
+                self.moving_updated = 0;
+                self.original_len = 0;
+            } else {
+                return None;
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.moving.len()
+            + self.pending_insertion.is_some() as usize
+            + self.remaining
+            + self.original_len;
+        (len, Some(len))
+    }
+}
+impl<'a, T> ExactSizeIterator for UpdatedLocations<'a, T> {}
+
+/// Iterates over the queued insertions of an [`InsertionSet`], borrowing their elements.
+///
+/// See [InsertionSet::iter_queued] for details
+#[derive(Debug)]
+pub struct QueuedIter<'a, T> {
+    /// The individual insertions that haven't been yielded yet
+    insertions: core::slice::Iter<'a, Insertion<T>>,
+    /// The number of individual insertions already yielded
+    consumed: usize,
+    /// The shared insertions that haven't been reached yet
+    shared: core::slice::Iter<'a, SharedInsertions<T>>,
+    /// The remaining indices of the current shared insertion
+    shared_indices: core::slice::Iter<'a, usize>,
+    /// The element of the current shared insertion
+    shared_element: Option<&'a T>,
+}
+impl<'a, T> Iterator for QueuedIter<'a, T> {
+    type Item = Insertion<&'a T>;
+
+    fn next(&mut self) -> Option<Insertion<&'a T>> {
+        loop {
+            if let Some(&index) = self.shared_indices.next() {
+                return Some(Insertion::new(index, self.shared_element.unwrap()));
+            }
+            match self.shared.as_slice().first() {
+                Some(shared) if shared.position == self.consumed => {
+                    self.shared.next();
+                    self.shared_indices = shared.indices.iter();
+                    self.shared_element = Some(&shared.element);
+                }
+                _ => break,
+            }
+        }
+        let insertion = self.insertions.next()?;
+        self.consumed += 1;
+        Some(Insertion::new(insertion.index, &insertion.element))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.insertions.len()
+            + self.shared_indices.len()
+            + self
+                .shared
+                .as_slice()
+                .iter()
+                .map(|shared| shared.indices.len())
+                .sum::<usize>();
+        (len, Some(len))
+    }
+}
+impl<'a, T> ExactSizeIterator for QueuedIter<'a, T> {}
+
+/// Iterates over the result of applying an [`InsertionSet`], without moving any memory.
+///
+/// See [InsertionSet::iter_applied] for details
+#[derive(Debug)]
+pub struct AppliedIter<'a, T> {
+    /// The original elements that haven't been yielded yet
+    target: core::slice::Iter<'a, T>,
+    /// The sorted insertions
+    insertions: Resolved<'a, T>,
+    /// The id of the next insertion to yield
+    next_insertion: usize,
+    /// The original index of the next element in `target`
+    next_original: usize,
+}
+impl<'a, T> Iterator for AppliedIter<'a, T> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        match self.insertions.get(self.next_insertion) {
+            Some((index, element)) if index <= self.next_original => {
+                self.next_insertion += 1;
+                Some(element)
+            }
+            _ => {
+                self.next_original += 1;
+                self.target.next()
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.target.len() + (self.insertions.len() - self.next_insertion);
+        (len, Some(len))
+    }
+}
+impl<'a, T> ExactSizeIterator for AppliedIter<'a, T> {}
+
+/// Moves the elements out of the result of applying an [`InsertionSet`], in their final order.
+///
+/// See [InsertionSet::drain_applied] for details
+#[derive(Debug)]
+pub struct DrainApplied<T> {
+    /// The original elements that haven't been yielded yet
+    target: alloc::vec::IntoIter<T>,
+    /// The sorted insertions that haven't been yielded yet
+    insertions: core::iter::Peekable<alloc::vec::IntoIter<Insertion<T>>>,
+    /// The original index of the next element in `target`
+    next_original: usize,
+}
+impl<T> Iterator for DrainApplied<T> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        let next_original = self.next_original;
+        if let Some(insertion) = self
+            .insertions
+            .next_if(|insertion| insertion.index <= next_original)
+        {
+            return Some(insertion.element);
+        }
+        self.next_original += 1;
+        self.target.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.target.len() + self.insertions.len();
+        (len, Some(len))
+    }
+}
+impl<T> ExactSizeIterator for DrainApplied<T> {}
+
+/// Statistics about the memory of the target, returned by [`InsertionSet::apply_reporting`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ApplyStats {
+    /// Whether the target had to be reallocated to make room for the insertions
+    pub reallocated: bool,
+    /// The capacity of the target before applying
+    pub old_capacity: usize,
+    /// The capacity of the target after applying
+    pub new_capacity: usize,
+    /// The number of original elements that were moved to make room for the insertions
+    pub elements_moved: usize,
+}
+
+/// An error caused by an insertion index that is out of bounds of the target
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct InsertionError {
+    /// The invalid insertion index
+    pub index: usize,
+    /// The length of the target
+    pub len: usize,
+}
+impl Display for InsertionError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid insertion index {} > len {}",
+            self.index, self.len
+        )
+    }
+}
+impl Error for InsertionError {}
+
+/// An error from applying insertions gathered from a fallible source
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ApplyError<E> {
+    /// The source of insertions failed
+    Source(E),
+    /// One of the insertions was out of bounds
+    Insertion(InsertionError),
+}
+impl<E: Display> Display for ApplyError<E> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ApplyError::Source(cause) => write!(f, "Failed to generate insertions: {}", cause),
+            ApplyError::Insertion(cause) => Display::fmt(cause, f),
+        }
+    }
+}
+impl<E: Debug + Display> Error for ApplyError<E> {}
+
+/// An error indicating the target doesn't have enough spare capacity to apply without allocating
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct NeedsAllocation {
+    /// The spare capacity required for all the insertions
+    pub required: usize,
+    /// The spare capacity that is actually available
+    pub available: usize,
+}
+impl Display for NeedsAllocation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Needs spare capacity for {} insertions, but only {} available",
+            self.required, self.available
+        )
+    }
+}
+impl Error for NeedsAllocation {}
+
+/// An error indicating the insertions would grow the target beyond its maximum length
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GrowthExceeded {
+    /// The length the target would have after applying the insertions
+    pub len: usize,
+    /// The maximum length allowed
+    pub max_len: usize,
+}
+impl GrowthExceeded {
+    /// The number of elements over the budget
+    #[inline]
+    pub fn excess(&self) -> usize {
+        self.len - self.max_len
+    }
+}
+impl Display for GrowthExceeded {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Resulting len {} exceeds the maximum {} by {}",
+            self.len,
+            self.max_len,
+            self.excess()
+        )
+    }
+}
+impl Error for GrowthExceeded {}
+
+/// The original location of an element (before a set of insertions are applied)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OriginalLocation {
+    /// The element was a queued insertion with the specified index
+    Insertion(usize),
+    /// The element was originally part of the vector
+    Original(usize),
+}
+
+/// Compute the updated locations of all elements (original + inserted).
+///
+/// See [InsertionSet::compute_updated_locations] for details
+pub fn compute_updated_locations<T, I, F>(target: &[T], mut insertions: I, mut updated: F)
+where
+    I: Iterator<Item = usize>,
+    I: ExactSizeIterator,
+    F: FnMut(OriginalLocation, usize),
+{
+    // This mirrors `apply_bulk_insertions` without actually shifting memory
+    let mut original_len = target.len();
+    let shifted_end = original_len
+        .checked_add(insertions.len())
+        .expect("capacity overflow");
+    let mut shifted_start = shifted_end;
+    let mut insertion_id = 0;
+    while original_len != shifted_start {
+        let insertion_index = insertions.next().expect("Expected more insertions!");
+        assert!(
+            insertion_index <= original_len,
+            "Invalid insertion index {} > len {}",
+            insertion_index,
+            original_len
+        );
+        let moved_memory = original_len - insertion_index;
+        if moved_memory > 0 {
+            assert!(
+                shifted_start >= moved_memory && insertion_index <= shifted_start - moved_memory
+            );
+            update_range(
+                insertion_index..original_len,
+                shifted_start - moved_memory,
+                &mut updated,
+            );
+            shifted_start -= moved_memory;
+            original_len = insertion_index;
+        }
+        assert!(shifted_start > original_len);
+        shifted_start -= 1;
+        updated(OriginalLocation::Insertion(insertion_id), shifted_start);
+        insertion_id += 1;
+    }
+    for original_index in 0..original_len {
+        updated(OriginalLocation::Original(original_index), original_index);
+    }
+    assert_eq!(insertions.len(), 0, "Unexpected insertions");
+}
+#[inline]
+fn update_range<F: FnMut(OriginalLocation, usize)>(
+    original: Range<usize>,
+    updated_start: usize,
+    func: &mut F,
+) {
+    for (updated, original_index) in (updated_start..).zip(original) {
+        func(OriginalLocation::Original(original_index), updated);
+    }
+}
+
+/// Rearrange the original and inserted elements according to a location map,
+/// like the one given by [InsertionSet::list_updated_locations].
+///
+/// This applies the same structural edits to a parallel array
+/// (possibly with a different element type) as the set that produced the map.
+/// The inserted elements are indexed by their [`OriginalLocation::Insertion`] id.
+///
+/// Panics unless the map places every original and inserted element exactly once,
+/// at a distinct final index in `0..original.len() + inserted.len()`.
+pub fn apply_location_map<T>(
+    original: Vec<T>,
+    inserted: Vec<T>,
+    map: &[(OriginalLocation, usize)],
+) -> Vec<T> {
+    let total_len = original.len() + inserted.len();
+    assert_eq!(
+        map.len(),
+        total_len,
+        "Location map doesn't match the number of elements"
+    );
+    let mut original = original.into_iter().map(Some).collect::<Vec<_>>();
+    let mut inserted = inserted.into_iter().map(Some).collect::<Vec<_>>();
+    let mut result = Vec::with_capacity(total_len);
+    result.resize_with(total_len, || None);
+    for &(location, updated) in map {
+        let element = match location {
+            OriginalLocation::Original(index) => original.get_mut(index),
+            OriginalLocation::Insertion(id) => inserted.get_mut(id),
+        }
+        .and_then(Option::take)
+        .unwrap_or_else(|| panic!("Invalid or duplicate location {:?}", location));
+        let slot = result
+            .get_mut(updated)
+            .unwrap_or_else(|| panic!("Invalid updated location {} >= len {}", updated, total_len));
+        assert!(slot.is_none(), "Duplicate updated location {}", updated);
+        *slot = Some(element);
+    }
+    // Every slot is filled, since there are exactly as many (distinct) locations as slots
+    result.into_iter().map(Option::unwrap).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn basic() {
+        /*
+         * For example, given the vector `[1, 4, 5, 7, 11]`
+         * and the InsertionSet `[(0, 0), (1, 2), (1, 3) (4, 9)]`:
+         */
+        let vector = vec![1, 4, 5, 7, 11];
+        let insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions.applied(vector), vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn updated_locations() {
+        /*
+         * For example, given the vector `[1, 4, 5, 7, 11]`
+         * and the InsertionSet `[(0, 0), (1, 2), (1, 3) (4, 9)]`:
+         */
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(
+            insertions.list_updated_locations(&vector),
+            vec![
+                (OriginalLocation::Insertion(0), 0),
+                (OriginalLocation::Original(0), 1),
+                (OriginalLocation::Insertion(1), 2),
+                (OriginalLocation::Insertion(2), 3),
+                (OriginalLocation::Original(1), 4),
+                (OriginalLocation::Original(2), 5),
+                (OriginalLocation::Original(3), 6),
+                (OriginalLocation::Insertion(3), 7),
+                (OriginalLocation::Original(4), 8),
+            ]
+        );
+    }
+    #[test]
+    fn apply_location_map() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let map = insertions.list_updated_locations(&vector);
+        let spans = vec!["a", "b", "c", "d", "e"];
+        let inserted_spans = vec!["0", "2", "3", "9"];
+        assert_eq!(
+            super::apply_location_map(spans, inserted_spans, &map),
+            vec!["0", "a", "2", "3", "b", "c", "d", "9", "e"]
+        );
+        // The map works in any order
+        let mut reversed = map.clone();
+        reversed.reverse();
+        assert_eq!(
+            super::apply_location_map(vector.clone(), vec![0, 2, 3, 9], &reversed),
+            insertions.applied(vector)
+        );
+    }
+    #[test]
+    #[should_panic(expected = "Duplicate updated location 0")]
+    fn apply_location_map_not_permutation() {
+        let map = [
+            (OriginalLocation::Original(0), 0),
+            (OriginalLocation::Insertion(0), 0),
+        ];
+        super::apply_location_map(vec!['a'], vec!['b'], &map);
+    }
+    #[test]
+    fn remap_search_result() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let found = vector.binary_search(&4);
+        assert_eq!(found, Ok(1));
+        assert_eq!(insertions.remap_search_result(found, vector.len()), Ok(4));
+        let missing = vector.binary_search(&8);
+        assert_eq!(missing, Err(4));
+        assert_eq!(
+            insertions.remap_search_result(missing, vector.len()),
+            Err(7)
+        );
+        let updated = insertions.applied(vector);
+        assert_eq!(updated.binary_search(&4), Ok(4));
+        assert_eq!(updated.binary_search(&8), Err(7));
+    }
+    /// Apply the insertions against a vector of length `n`,
+    /// returning the total number of elements moved or written.
+    fn count_moves(n: usize, indexes: &[usize]) -> usize {
+        let mut target = (0..n).collect::<Vec<usize>>();
+        let mut insertions = indexes
+            .iter()
+            .map(|&index| (index, usize::MAX))
+            .collect::<InsertionSet<usize>>();
+        insertions.sort();
+        let mut total_moves = 0;
+        let shifter = BulkShifter::new(&mut target, insertions.desired_insertions());
+        shift_insertions(
+            shifter,
+            DrainReverse::new(&mut insertions.insertions),
+            |event| match event {
+                ShiftEvent::Shift { count, .. } => total_moves += count,
+                ShiftEvent::Push { .. } => total_moves += 1,
+            },
+        );
+        assert_eq!(target.len(), n + indexes.len());
+        total_moves
+    }
+    #[test]
+    fn linear_movement() {
+        let n = 1000;
+        // A simple LCG, so the "random" shape is deterministic
+        let mut state = 12345u64;
+        let scattered = (0..500)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 33) as usize % (n + 1)
+            })
+            .collect::<Vec<_>>();
+        let shapes: Vec<Vec<usize>> = vec![
+            vec![],
+            vec![0; 500],
+            vec![n; 500],
+            (0..=n).collect(),
+            (0..=n).rev().collect(),
+            (0..=n).step_by(7).collect(),
+            scattered,
+        ];
+        for indexes in &shapes {
+            let m = indexes.len();
+            let total_moves = count_moves(n, indexes);
+            assert!(
+                total_moves <= n + m,
+                "Moved {} elements, but n + m = {}",
+                total_moves,
+                n + m
+            );
+        }
+    }
+    #[test]
+    fn boxed_trait_objects() {
+        let vector: Vec<Box<dyn Debug>> = vec![Box::new(1), Box::new("two")];
+        let mut insertions = InsertionSet::<Box<dyn Debug>>::new();
+        insertions.insert(2, Box::new(3.5));
+        insertions.insert(0, Box::new('z'));
+        let result = insertions.applied(vector);
+        assert_eq!(format!("{:?}", result), r#"['z', 1, "two", 3.5]"#);
+
+        let mut names = InsertionSet::<Box<str>>::new();
+        names.insert_boxed(1, "b");
+        names.insert_boxed(0, String::from("a"));
+        let result = names.applied(vec![Box::from("c")]);
+        assert_eq!(result, vec![Box::from("a"), Box::from("c"), Box::from("b")]);
+    }
+    #[test]
+    fn apply_limited() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut partial = vector.clone();
+        insertions.apply_limited(&mut partial, 2);
+        assert_eq!(partial, vec![0, 1, 2, 4, 5, 7, 11]);
+        assert_eq!(insertions.desired_insertions(), 2);
+        insertions.apply(&mut partial);
+        assert_eq!(partial, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        let full = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>()
+            .applied(vector);
+        assert_eq!(partial, full);
+    }
+    #[test]
+    fn insert_many_same() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(2, 7);
+        insertions.insert_many_same(&[0, 2, 4], 0);
+        insertions.insert(2, 8);
+        assert_eq!(insertions.desired_insertions(), 5);
+        assert_eq!(
+            insertions.applied(vec![1, 2, 3, 4]),
+            vec![0, 1, 2, 7, 0, 8, 3, 4, 0]
+        );
+    }
+    #[test]
+    fn tagged_stability() {
+        use crate::testing::*;
+        let mut insertions = InsertionSet::new();
+        insert_tagged(&mut insertions, 2, 'c');
+        insert_tagged(&mut insertions, 0, 'a');
+        insert_tagged(&mut insertions, 2, 'd');
+        insert_tagged(&mut insertions, 0, 'b');
+        let result = insertions.applied(tag_originals(vec!['x', 'y', 'z']));
+        assert_stable(&result);
+        let values = result.iter().map(|tagged| tagged.value).collect::<String>();
+        assert_eq!(values, "abxycdz");
+
+        let unstable = vec![
+            Tagged::inserted(1, 'b'),
+            Tagged::inserted(0, 'a'),
+            Tagged::original('x'),
+        ];
+        assert_eq!(find_unstable(&unstable), Some(1));
+        assert!(std::panic::catch_unwind(|| assert_stable(&unstable)).is_err());
+    }
+    #[test]
+    fn distinct_insertion_points() {
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions.distinct_insertion_points(), 3);
+        assert_eq!(InsertionSet::<u32>::new().distinct_insertion_points(), 0);
+    }
+    #[test]
+    fn apply_filling_holes() {
+        let mut target = vec![Some(1), None, Some(2), None, Some(3), Some(4)];
+        let mut insertions = [(0, 10), (2, 20), (5, 30), (4, 40)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_filling_holes(&mut target);
+        assert_eq!(
+            target,
+            vec![
+                Some(1),
+                Some(10),
+                Some(2),
+                Some(20),
+                Some(40),
+                Some(3),
+                Some(30),
+                Some(4)
+            ]
+        );
+    }
+    #[test]
+    fn iter_updated_locations() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3), (5, 12)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut expected = Vec::new();
+        insertions.compute_updated_locations(&vector, |original, updated| {
+            expected.push((original, updated))
+        });
+        let iter = insertions.iter_updated_locations(vector.len());
+        assert_eq!(iter.len(), expected.len());
+        assert_eq!(iter.collect::<Vec<_>>(), expected);
+    }
+    #[test]
+    fn apply_then_dedup() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(1, 1), (3, 6), (4, 7), (5, 11)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_then_dedup(&mut target, |a, b| a == b);
+        assert_eq!(target, vec![1, 4, 5, 6, 7, 11]);
+
+        // Compares against the last kept element, including the original duplicates
+        let mut target = vec![1, 1, 2, 10];
+        let mut insertions = [(4, 11), (0, 0), (4, 12)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_then_dedup(&mut target, |a, b| b - a <= 1);
+        assert_eq!(target, vec![0, 2, 10, 12]);
+        let mut expected = vec![0, 1, 1, 2, 10, 11, 12];
+        expected.dedup_by(|b, a| *b - *a <= 1);
+        assert_eq!(target, expected);
+
+        // Duplicates removed before the insertions make room for them, so nothing reallocates
+        let mut target = Vec::with_capacity(6);
+        target.extend([1, 1, 1, 3, 5, 7]);
+        let ptr = target.as_ptr();
+        let mut insertions = [(4, 4), (6, 8)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_then_dedup(&mut target, |a, b| a == b);
+        assert_eq!(target, vec![1, 3, 4, 5, 7, 8]);
+        assert_eq!((target.as_ptr(), target.capacity()), (ptr, 6));
+    }
+    #[test]
+    fn apply_then_dedup_matches_dedup_by() {
+        // Boxed, so leaks and double drops show up under miri
+        let original = || {
+            [1, 1, 2, 4, 4, 4, 5, 9]
+                .iter()
+                .map(|&value| Box::new(value))
+                .collect::<Vec<_>>()
+        };
+        let queued: &[&[(usize, u32)]] = &[
+            &[],
+            &[(0, 1)],
+            &[(0, 0), (8, 9), (8, 9), (8, 10)],
+            &[(2, 1), (2, 2), (3, 3), (3, 4), (6, 7), (6, 8)],
+            &[(0, 1), (1, 1), (2, 2), (2, 3), (4, 4), (5, 5), (8, 9)],
+        ];
+        for &queued in queued {
+            for capacity in [8, 10, 20] {
+                let mut expected = original();
+                let mut insertions = queued
+                    .iter()
+                    .map(|&(index, value)| (index, Box::new(value)))
+                    .collect::<InsertionSet<_>>();
+                insertions.clone().apply(&mut expected);
+                expected.dedup_by(|b, a| a == b);
+                let mut target = Vec::with_capacity(capacity);
+                target.extend(original());
+                insertions.apply_then_dedup(&mut target, |a, b| a == b);
+                assert_eq!(target, expected, "{:?} with capacity {}", queued, capacity);
+                assert!(insertions.is_empty());
+            }
+        }
+    }
+    #[test]
+    fn will_reallocate() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(0, 0);
+        insertions.insert(1, 2);
+        let mut target = Vec::with_capacity(4);
+        target.extend([1, 3, 5]);
+        assert!(insertions.will_reallocate(&target));
+        target.reserve(2);
+        assert!(!insertions.will_reallocate(&target));
+        let capacity = target.capacity();
+        insertions.apply(&mut target);
+        assert_eq!(target.capacity(), capacity);
+    }
+    #[test]
+    fn apply_partitioning() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9), (5, 12)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut extracted = Vec::new();
+        insertions.apply_partitioning(&mut target, &mut extracted, |&element| element > 4);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 9, 12]);
+        assert_eq!(extracted, vec![5, 7, 11]);
+
+        // Extractions before the insertions make room for them, so nothing reallocates
+        let mut target = Vec::with_capacity(5);
+        target.extend([10, 1, 20, 4, 5]);
+        let ptr = target.as_ptr();
+        let mut insertions = [(2, 2), (3, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut extracted = Vec::new();
+        insertions.apply_partitioning(&mut target, &mut extracted, |&element| element >= 10);
+        assert_eq!(target, vec![1, 2, 3, 4, 5]);
+        assert_eq!(extracted, vec![10, 20]);
+        assert_eq!((target.as_ptr(), target.capacity()), (ptr, 5));
+    }
+    #[test]
+    fn apply_partitioning_in_place() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        // Boxed, so leaks and double drops show up under miri
+        let original = || (0..8).map(Box::new).collect::<Vec<_>>();
+        let queued: &[&[(usize, u32)]] = &[
+            &[],
+            &[(0, 10), (0, 11), (8, 18)],
+            &[(1, 11), (2, 12), (2, 13), (5, 15), (8, 18)],
+            &[(0, 10), (1, 11), (2, 12), (3, 13), (4, 14), (5, 15)],
+        ];
+        let extract = |element: &u32| [0, 3, 6].contains(element);
+        for &queued in queued {
+            for capacity in [8, 10, 20] {
+                let insertions = queued
+                    .iter()
+                    .map(|&(index, value)| (index, Box::new(value)))
+                    .collect::<InsertionSet<_>>();
+                let mut expected = Vec::new();
+                let mut expected_extracted = Vec::new();
+                let mut pending = queued.iter().peekable();
+                for (original_index, element) in original().into_iter().enumerate() {
+                    while let Some(&(_, value)) = pending.next_if(|&&(i, _)| i == original_index) {
+                        expected.push(Box::new(value));
+                    }
+                    if extract(&element) {
+                        expected_extracted.push(element);
+                    } else {
+                        expected.push(element);
+                    }
+                }
+                expected.extend(pending.map(|&(_, value)| Box::new(value)));
+                let mut target = Vec::with_capacity(capacity);
+                target.extend(original());
+                let mut extracted = Vec::new();
+                insertions
+                    .clone()
+                    .apply_partitioning(&mut target, &mut extracted, |element| extract(element));
+                assert_eq!(target, expected, "{:?} with capacity {}", queued, capacity);
+                assert_eq!(extracted, expected_extracted);
+
+                // A panicking predicate leaves everything it hasn't reached in the target
+                let mut target = Vec::with_capacity(capacity);
+                target.extend(original());
+                let mut extracted = Vec::new();
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    insertions
+                        .clone()
+                        .apply_partitioning(&mut target, &mut extracted, |element| {
+                            assert_ne!(**element, 4);
+                            extract(element)
+                        })
+                }));
+                assert!(result.is_err());
+                assert_eq!(extracted, vec![Box::new(0), Box::new(3)]);
+                assert_eq!(
+                    target.len() + extracted.len(),
+                    8 + queued.iter().filter(|&&(i, _)| i <= 4).count()
+                );
+            }
+        }
+    }
+    #[test]
+    fn apply_with_headroom() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_with_headroom(&mut target, 100);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        assert!(target.capacity() >= target.len() + 100);
+    }
+    #[test]
+    fn try_finish_shifter() {
+        let mut target = vec![1, 4, 5];
+        let mut shifter = BulkShifter::new(&mut target, 2);
+        shifter.shift_original(2);
+        shifter.push_shifted(3);
+        let mut shifter = shifter.try_finish().unwrap_err();
+        assert_eq!(shifter.shifted_elements(), &[3, 5]);
+        shifter.shift_original(1);
+        shifter.push_shifted(2);
+        let target = shifter.try_finish().unwrap();
+        assert_eq!(*target, vec![1, 2, 4, 3, 5]);
+    }
+    #[test]
+    fn apply_via_index_map() {
+        // The first two elements are metadata that the indices skip over
+        let mut target = vec![-1, -2, 1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<i32>>();
+        insertions.apply_via_index_map(&mut target, |index| index + 2);
+        assert_eq!(target, vec![-1, -2, 0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn recording_delta() {
+        let original = vec![1, 4, 5, 7, 11];
+        let insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut target = original.clone();
+        let delta = insertions.apply_recording_delta(&mut target);
+        assert_eq!(delta.placements, vec![(0, 0), (2, 2), (3, 3), (7, 9)]);
+        assert_eq!(delta.original_len, 5);
+        let mut replayed = original;
+        delta.replay(&mut replayed);
+        assert_eq!(replayed, target);
+    }
+    #[test]
+    fn max_inserted_position() {
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions.max_inserted_position(5), Some(7));
+        assert_eq!(InsertionSet::<u32>::new().max_inserted_position(5), None);
+    }
+    #[test]
+    #[cfg(feature = "std")]
+    fn apply_building_index() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let make_insertions = || {
+            [(0, 0), (1, 2), (1, 3), (4, 9)]
+                .iter()
+                .cloned()
+                .collect::<InsertionSet<u32>>()
+        };
+        let expected = make_insertions()
+            .list_updated_locations(&vector)
+            .into_iter()
+            .map(|(original, updated)| (updated, original))
+            .collect::<HashMap<_, _>>();
+        let mut target = vector.clone();
+        let index = make_insertions().apply_building_index(&mut target, false);
+        assert_eq!(index, expected);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+
+        let mut target = vector;
+        let index = make_insertions().apply_building_index(&mut target, true);
+        let inserted_only = expected
+            .into_iter()
+            .filter(|(_, original)| matches!(original, OriginalLocation::Insertion(_)))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(index, inserted_only);
+    }
+    #[test]
+    fn apply_with_scratch() {
+        let mut insertions = [(0, 0), (4, 9), (1, 2)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.insert_many_same(&[1], 3);
+        let mut scratch = Vec::new();
+        let mut first = vec![1, 4, 5, 7, 11];
+        insertions.apply_with_scratch(&mut first, &mut scratch);
+        assert_eq!(first, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        let mut second = vec![10, 40, 50, 70, 110];
+        insertions.apply_with_scratch(&mut second, &mut scratch);
+        assert_eq!(second, vec![0, 10, 2, 3, 40, 50, 70, 9, 110]);
+        assert_eq!(insertions.desired_insertions(), 4);
+        assert!(scratch.capacity() >= 4);
+        // Later calls reuse the same buffer
+        let buffer = scratch.as_ptr();
+        insertions.apply_with_scratch(&mut vec![1, 4, 5, 7, 11], &mut scratch);
+        assert_eq!(scratch.as_ptr(), buffer);
+    }
+    #[test]
+    fn apply_with_scratch_everything() {
+        let mut scratch = Vec::new();
+        for with_seqs in [false, true] {
+            let mut insertions = InsertionSet::new();
+            insertions.insert(3, 30);
+            if with_seqs {
+                insertions.insert_seq(1, 2, 12);
+                insertions.insert_seq(1, 1, 11);
+            }
+            insertions.insert(1, 10);
+            insertions.insert_many_same(&[1, 5], 15);
+            insertions.insert_anchored(InsertionAnchor::FromEnd(4), 40);
+            insertions.pad_to(14, 0);
+            for len in [5, 6, 8] {
+                let original = (100..100 + len).collect::<Vec<u32>>();
+                let mut expected = original.clone();
+                insertions.clone().apply(&mut expected);
+                let mut target = original;
+                insertions.apply_with_scratch(&mut target, &mut scratch);
+                assert_eq!(target, expected);
+            }
+            insertions.discard();
+        }
+    }
+    #[test]
+    fn apply_to_empty() {
+        let insertions = [(0, 'a'), (0, 'b'), (0, 'c')]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<char>>();
+        let result = insertions.applied(Vec::new());
+        assert_eq!(result, vec!['a', 'b', 'c']);
+        assert_eq!(result.len(), 3);
+
+        let mut insertions = InsertionSet::new();
+        insertions.insert(0, 'a');
+        assert_eq!(insertions.applied(vec!['b']), vec!['a', 'b']);
+        assert_eq!(InsertionSet::new().applied(Vec::<char>::new()), vec![]);
+    }
+    #[test]
+    fn empty_shifter() {
+        let mut target = Vec::<u32>::new();
+        let shifter = BulkShifter::new(&mut target, 0);
+        assert!(shifter.is_finished());
+        assert!(shifter.finish().is_empty());
+
+        let mut shifter = BulkShifter::new(&mut target, 2);
+        assert!(shifter.is_empty());
+        assert!(!shifter.is_finished());
+        assert_eq!(shifter.shift_original(0), 0);
+        shifter.push_shifted(2);
+        assert!(!shifter.is_finished());
+        shifter.push_shifted(1);
+        assert!(shifter.is_finished());
+        assert_eq!(*shifter.finish(), vec![1, 2]);
+    }
+    #[test]
+    fn apply_with_counts() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions.apply_with_counts(&mut target), (5, 4));
+        assert_eq!(target.len(), 9);
+        // The originals before the first insertion are preserved without moving
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = InsertionSet::new();
+        insertions.insert(3, 6);
+        insertions.insert(5, 12);
+        assert_eq!(insertions.apply_with_counts(&mut target), (5, 2));
+        assert_eq!(target, vec![1, 4, 5, 6, 7, 11, 12]);
+        let mut target = vec![1, 4, 5];
+        assert_eq!(InsertionSet::new().apply_with_counts(&mut target), (3, 0));
+    }
+    #[test]
+    fn apply_observing_moves() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut moves = Vec::new();
+        insertions.apply_observing_moves(&mut target, |element, old, new| {
+            moves.push((*element, old, new));
+            *element *= 10;
+        });
+        assert_eq!(moves, vec![(4, 1, 3), (5, 2, 4), (7, 3, 5), (11, 4, 7)]);
+        assert_eq!(target, vec![1, 2, 3, 40, 50, 70, 9, 110]);
+    }
+    #[test]
+    fn try_from_tuples() {
+        let insertions =
+            InsertionSet::try_from_tuples(vec![(4, 9), (1, 2), (0, 0), (1, 3)], 5).unwrap();
+        assert_eq!(
+            insertions.applied(vec![1, 4, 5, 7, 11]),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11]
+        );
+        let error = InsertionSet::try_from_tuples(vec![(4, 9), (6, 2), (7, 0)], 5)
+            .err()
+            .unwrap();
+        assert_eq!(error, InsertionError { index: 6, len: 5 });
+        assert_eq!(error.to_string(), "Invalid insertion index 6 > len 5");
+    }
+    #[test]
+    fn tail_shift() {
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9), (5, 12)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions.tail_shift(5), 4);
+        let result = insertions.applied(vec![1, 4, 5, 7, 11]);
+        assert_eq!(result.iter().position(|&x| x == 11), Some(4 + 4));
+    }
+    #[test]
+    fn apply_deref() {
+        struct Guarded(Vec<u32>);
+        impl std::ops::Deref for Guarded {
+            type Target = Vec<u32>;
+            fn deref(&self) -> &Vec<u32> {
+                &self.0
+            }
+        }
+        impl DerefMut for Guarded {
+            fn deref_mut(&mut self) -> &mut Vec<u32> {
+                &mut self.0
+            }
+        }
+        let mut target = Guarded(vec![1, 4, 5, 7, 11]);
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_deref(&mut target);
+        assert_eq!(target.0, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn try_apply_no_alloc() {
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut target = Vec::with_capacity(6);
+        target.extend([1, 4, 5, 7, 11]);
+        let capacity = target.capacity();
+        assert_eq!(
+            insertions.try_apply_no_alloc(&mut target),
+            Err(NeedsAllocation {
+                required: 4,
+                available: capacity - 5
+            })
+        );
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+        assert_eq!(target.capacity(), capacity);
+        target.reserve(4);
+        let capacity = target.capacity();
+        assert_eq!(insertions.try_apply_no_alloc(&mut target), Ok(()));
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        assert_eq!(target.capacity(), capacity);
+
+        // Padding needs room too
+        let mut insertions = InsertionSet::new();
+        insertions.pad_to(100, 0);
+        let mut target = Vec::with_capacity(4);
+        target.extend([1, 2, 3]);
+        let capacity = target.capacity();
+        assert!(insertions.will_reallocate(&target));
+        assert_eq!(
+            insertions.try_apply_no_alloc(&mut target),
+            Err(NeedsAllocation {
+                required: 97,
+                available: capacity - 3
+            })
+        );
+        assert_eq!(target, vec![1, 2, 3]);
+        assert_eq!(target.capacity(), capacity);
+        insertions.discard();
+    }
+    #[test]
+    fn apply_to_many() {
+        use std::cell::Cell;
+        thread_local!(static CLONES: Cell<usize> = const { Cell::new(0) });
+        #[derive(Debug, PartialEq)]
+        struct Counted(u32);
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.with(|clones| clones.set(clones.get() + 1));
+                Counted(self.0)
+            }
+        }
+        let mut insertions = InsertionSet::new();
+        insertions.insert(0, Counted(0));
+        insertions.insert_many_same(&[1, 4], Counted(9));
+        let originals = || vec![Counted(1), Counted(4), Counted(5), Counted(7)];
+        let mut targets = vec![originals(), originals(), originals()];
+        insertions.apply_to_many(&mut targets);
+        // Three clones for each of the first two targets, and one to expand the last
+        assert_eq!(CLONES.with(Cell::get), 7);
+        let expected = [0, 1, 9, 4, 5, 7, 9].map(Counted).to_vec();
+        assert!(targets.iter().all(|target| *target == expected));
+
+        // Anchored insertions are resolved against each target separately
+        let mut insertions = InsertionSet::new();
+        insertions.insert(0, Counted(0));
+        insertions.insert_anchored(InsertionAnchor::FromEnd(0), Counted(12));
+        let mut targets = vec![originals(), vec![Counted(1)]];
+        insertions.apply_to_many(&mut targets);
+        assert_eq!(targets[0], [0, 1, 4, 5, 7, 12].map(Counted).to_vec());
+        assert_eq!(targets[1], [0, 1, 12].map(Counted).to_vec());
+        InsertionSet::<Counted>::new().apply_to_many(&mut []);
+    }
+    #[test]
+    fn apply_returning_appended() {
+        let mut target = vec![1, 4, 5];
+        let mut insertions = [(3, 7), (3, 9), (3, 11)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let appended = insertions.apply_returning_appended(&mut target);
+        assert_eq!(appended, Some(3..6));
+        assert_eq!(target[appended.unwrap()], [7, 9, 11]);
+
+        let mut interspersed = [(0, 0), (3, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(interspersed.apply_returning_appended(&mut target), None);
+        assert_eq!(target, vec![0, 1, 4, 5, 9, 7, 9, 11]);
+    }
+    #[test]
+    fn byte_len_guard() {
+        use crate::shift::assert_valid_byte_len;
+        let max = isize::MAX as usize;
+        assert_valid_byte_len::<u8>(max);
+        assert_valid_byte_len::<u64>(max / 8);
+        assert_valid_byte_len::<()>(usize::MAX);
+        for result in [
+            std::panic::catch_unwind(|| assert_valid_byte_len::<u8>(max + 1)),
+            std::panic::catch_unwind(|| assert_valid_byte_len::<u64>(max / 8 + 1)),
+            std::panic::catch_unwind(|| assert_valid_byte_len::<[u8; 3]>(usize::MAX)),
+        ] {
+            assert!(result.is_err());
+        }
+    }
+    #[test]
+    fn into_shifter() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let (mut shifter, mut sorted) = insertions.into_shifter(&mut target);
+        assert_eq!(
+            sorted
+                .iter()
+                .map(|insertion| insertion.index)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 1, 4]
+        );
+        while let Some(Insertion { index, element }) = sorted.pop() {
+            shifter.shift_original(index);
+            shifter.push_shifted(element);
+        }
+        shifter.finish();
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn apply_arc() {
+        let make_insertions = || {
+            [(0, 0), (1, 2), (1, 3), (4, 9)]
+                .iter()
+                .cloned()
+                .collect::<InsertionSet<u32>>()
+        };
+        let mut unique = Arc::new(vec![1, 4, 5, 7, 11]);
+        let address = Arc::as_ptr(&unique);
+        make_insertions().apply_arc(&mut unique);
+        assert_eq!(*unique, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        assert_eq!(Arc::as_ptr(&unique), address);
+
+        let mut shared = Arc::new(vec![1, 4, 5, 7, 11]);
+        let other = Arc::clone(&shared);
+        make_insertions().apply_arc(&mut shared);
+        assert_eq!(*shared, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        assert_eq!(*other, vec![1, 4, 5, 7, 11]);
+        assert!(!Arc::ptr_eq(&shared, &other));
+    }
+    #[test]
+    fn updated_locations_with_deletions() {
+        let mut insertions = [(2, 'x'), (1, 'y')]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<char>>();
+        let mut locations = Vec::new();
+        insertions.compute_updated_locations_with_deletions(4, &[1, 3], |original, updated| {
+            locations.push((original, updated))
+        });
+        assert_eq!(
+            locations,
+            vec![
+                (OriginalLocation::Original(0), Some(0)),
+                (OriginalLocation::Insertion(0), Some(1)),
+                (OriginalLocation::Original(1), None),
+                (OriginalLocation::Insertion(1), Some(2)),
+                (OriginalLocation::Original(2), Some(3)),
+                (OriginalLocation::Original(3), None),
+            ]
+        );
+    }
+    #[test]
+    fn apply_yielding_changes() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let changes = insertions
+            .apply_yielding_changes(&mut target)
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![0, 2, 3, 7]);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    /// An iterator that lies about its length
+    struct LyingIter<I>(I, usize);
+    impl<I: Iterator> Iterator for LyingIter<I> {
+        type Item = I::Item;
+        fn next(&mut self) -> Option<I::Item> {
+            self.0.next()
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.1, Some(self.1))
+        }
+    }
+    impl<I: Iterator> ExactSizeIterator for LyingIter<I> {}
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn apply_overflowing_len() {
+        let mut target = vec![(), ()];
+        apply_bulk_insertions(
+            &mut target,
+            LyingIter(core::iter::empty::<Insertion<()>>(), usize::MAX),
+        );
+    }
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn updated_locations_overflowing_len() {
+        compute_updated_locations(
+            &[(), ()],
+            LyingIter(core::iter::empty(), usize::MAX - 1),
+            |_, _| {},
+        );
+    }
+    #[test]
+    fn apply_wrong_len() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        // Boxed, so Miri catches any leaked or double dropped elements
+        let insertions = || {
+            [(4, 9), (1, 3), (1, 2), (0, 0)]
+                .into_iter()
+                .map(|(index, element)| Insertion::new(index, Box::new(element)))
+                .collect::<Vec<_>>()
+        };
+        let original = || [1, 4, 5, 7, 11].map(Box::new).to_vec();
+        // Too few, so the insertions that were actually yielded are rolled back
+        for yielded in 0..4 {
+            let mut target = original();
+            let error = catch_unwind(AssertUnwindSafe(|| {
+                apply_bulk_insertions(
+                    &mut target,
+                    LyingIter(insertions().into_iter().take(yielded), 4),
+                )
+            }))
+            .unwrap_err();
+            assert_eq!(
+                *error.downcast_ref::<String>().unwrap(),
+                format!(
+                    "Expected 4 insertions from the ExactSizeIterator, but only got {}",
+                    yielded
+                )
+            );
+            assert_eq!(target, original());
+        }
+        // Too many, so everything is rolled back (and the extras are never applied)
+        let mut target = original();
+        let error = catch_unwind(AssertUnwindSafe(|| {
+            apply_bulk_insertions(&mut target, LyingIter(insertions().into_iter(), 1))
+        }))
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<String>().unwrap(),
+            "Expected 1 insertions from the ExactSizeIterator, but got more"
+        );
+        assert_eq!(target, original());
+    }
+    #[test]
+    fn apply_untrusted() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let insertions = vec![
+            Insertion::new(4, 9),
+            Insertion::new(1, 3),
+            Insertion::new(1, 2),
+            Insertion::new(0, 0),
+        ];
+        apply_bulk_insertions_untrusted(&mut target, LyingIter(insertions.into_iter(), 1));
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn location_map_len() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(
+            insertions.location_map_len(vector.len()),
+            insertions.list_updated_locations(&vector).len()
+        );
+    }
+    #[test]
+    #[should_panic(expected = "Invalid insertion index 9 > len 5 for element Some(\"bad\")")]
+    fn apply_debug() {
+        let mut target = vec![None; 5];
+        let mut insertions = [(0, Some("good")), (9, Some("bad"))]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<_>>();
+        insertions.apply_debug(&mut target);
+    }
+    #[test]
+    fn into_sorted_tuples() {
+        let insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(
+            insertions.into_sorted_tuples(),
+            vec![(0, 0), (1, 2), (1, 3), (4, 9)]
+        );
+    }
+    #[test]
+    fn try_apply_from() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let source = (0..5).map(|i| {
+            if i == 2 {
+                Err("parse error")
+            } else {
+                Ok(Insertion::new(i, i * 10))
+            }
+        });
+        assert_eq!(
+            InsertionSet::try_apply_from(&mut target, source),
+            Err(ApplyError::Source("parse error"))
+        );
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+        assert_eq!(target.capacity(), 5);
+
+        let source = [Ok(Insertion::new(6, 0))];
+        assert_eq!(
+            InsertionSet::try_apply_from::<(), _>(&mut target, source),
+            Err(ApplyError::Insertion(InsertionError { index: 6, len: 5 }))
+        );
+        let source = [Ok::<_, ()>(Insertion::new(4, 9)), Ok(Insertion::new(0, 0))];
+        assert_eq!(InsertionSet::try_apply_from(&mut target, source), Ok(()));
+        assert_eq!(target, vec![0, 1, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn effects_equal() {
+        let mut first = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut second = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert!(first.effects_equal(&mut second, 5));
+        let mut reordered = [(0, 0), (1, 3), (1, 2), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert!(!first.effects_equal(&mut reordered, 5));
+    }
+    #[test]
+    fn apply_validated() {
+        let make_insertions =
+            |pairs: &[(usize, u32)]| pairs.iter().cloned().collect::<InsertionSet<u32>>();
+        let sorted = |element: &u32, predecessor: Option<&u32>| {
+            predecessor.is_none_or(|&predecessor| predecessor <= *element)
+        };
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut valid = make_insertions(&[(0, 0), (1, 2), (1, 3), (4, 9)]);
+        let mut invalid = make_insertions(&[(0, 0), (1, 3), (1, 2), (4, 9)]);
+        assert_eq!(invalid.apply_validated(&mut target, sorted), Err(1));
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+        assert_eq!(valid.apply_validated(&mut target, sorted), Ok(()));
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn applied_converting() {
+        let insertions = [(0, 0), (1, 2), (1, -3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<i32>>();
+        let result: Vec<i64> = insertions.applied_converting(vec![1, 4, 5, 7, 1 << 40]);
+        assert_eq!(result, vec![0, 1, 2, -3, 4, 5, 7, 9, 1 << 40]);
+    }
+    #[test]
+    fn longest_dense_run() {
+        let mut insertions = [9, 4, 0, 1, 5, 3, 6, 6, 7, 8]
+            .iter()
+            .map(|&index| (index, ()))
+            .collect::<InsertionSet<()>>();
+        assert_eq!(insertions.longest_dense_run(), 3..7);
+        assert_eq!(InsertionSet::<()>::new().longest_dense_run(), 0..0);
+    }
+    #[test]
+    fn apply_returning_unused() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let unused = insertions.apply_returning_unused(&mut target, 3);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 11]);
+        assert_eq!(insertions.desired_insertions(), 0);
+        assert_eq!(unused.len(), 1);
+        assert_eq!((unused[0].index, unused[0].element), (7, 9));
+        apply_bulk_insertions(&mut target, unused.into_iter().rev());
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn from_diff() {
+        let old = vec![1, 4, 5, 7, 11];
+        let new = vec![0, 1, 2, 3, 4, 5, 7, 9, 11, 12];
+        let insertions = InsertionSet::from_diff(&old, &new).unwrap();
+        assert_eq!(insertions.applied(old.clone()), new);
+        assert!(InsertionSet::from_diff(&old, &[1, 5, 4, 7, 11]).is_none());
+        assert!(InsertionSet::from_diff(&old, &[1, 4, 5]).is_none());
+    }
+    #[test]
+    fn apply_with_element_source() {
+        let mut target = vec![10, 20, 30];
+        let mut insertions = [3, 0, 1, 1]
+            .iter()
+            .map(|&index| (index, ()))
+            .collect::<InsertionSet<()>>();
+        insertions.apply_with_element_source(&mut target, 1..);
+        assert_eq!(target, vec![4, 10, 3, 2, 20, 30, 1]);
+    }
+    #[test]
+    fn pad_to() {
+        let mut insertions = InsertionSet::new();
+        insertions.pad_to(7, 0);
+        insertions.insert(1, 9);
+        insertions.insert(3, 8);
+        assert_eq!(insertions.applied(vec![1, 2, 3]), vec![1, 9, 2, 3, 8, 0, 0]);
+
+        let mut insertions = InsertionSet::new();
+        insertions.insert(0, 9);
+        insertions.pad_to(2, 0);
+        assert_eq!(insertions.applied(vec![1, 2, 3]), vec![9, 1, 2, 3]);
+
+        let mut insertions = InsertionSet::new();
+        insertions.pad_to(5, 0);
+        insertions.insert_seq(3, 7, 8);
+        assert_eq!(insertions.applied(vec![1, 2, 3]), vec![1, 2, 3, 8, 0]);
+    }
+    #[test]
+    fn for_each_final() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3), (5, 12)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut visited = Vec::new();
+        insertions.for_each_final(&vector, |original, &element| {
+            visited.push((original, element))
+        });
+        let locations = insertions.list_updated_locations(&vector);
+        let result = insertions.applied(vector);
+        assert_eq!(
+            visited
+                .iter()
+                .map(|&(original, _)| original)
+                .collect::<Vec<_>>(),
+            locations
+                .iter()
+                .map(|&(original, _)| original)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            visited
+                .iter()
+                .map(|&(_, element)| element)
+                .collect::<Vec<_>>(),
+            result
+        );
+    }
+    #[test]
+    fn insert_seq() {
+        let build = |order: &[(usize, u64, char)]| {
+            let mut insertions = InsertionSet::new();
+            insertions.insert(1, 'a');
+            for &(index, seq, element) in order {
+                insertions.insert_seq(index, seq, element);
+            }
+            insertions.insert_many_same(&[1], 'z');
+            insertions.applied(vec!['x', 'y'])
+        };
+        let queued = [(1, 1, 'b'), (1, 2, 'c'), (0, 5, 'd'), (2, 3, 'e')];
+        let merged = [(2, 3, 'e'), (1, 2, 'c'), (0, 5, 'd'), (1, 1, 'b')];
+        assert_eq!(build(&queued), vec!['d', 'x', 'a', 'z', 'b', 'c', 'y', 'e']);
+        assert_eq!(build(&queued), build(&merged));
+    }
+    #[test]
+    fn apply_transactional() {
+        let original = vec![1, 4, 5, 7, 11];
+        let mut target = original.clone();
+        let insertions = vec![(0, 0), (1, 2), (1, 3), (4, 9), (5, 12)]
+            .into_iter()
+            .collect::<InsertionSet<_>>();
+        let token = insertions.apply_transactional(&mut target);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11, 12]);
+        assert_eq!(token.len(), 5);
+        assert_eq!(revert(&mut target, token), vec![0, 2, 3, 9, 12]);
+        assert_eq!(target, original);
+    }
+    #[test]
+    fn revert_reuses_allocation() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let token = InsertionSet::from_iter([(0, 0), (5, 12)]).apply_transactional(&mut target);
+        let buffer = target.as_ptr();
+        assert_eq!(revert(&mut target, token), vec![0, 12]);
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+        assert_eq!(target.as_ptr(), buffer);
+    }
+    #[test]
+    fn revert_invalid_token() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        let original = vec![0, 1, 2, 3, 4, 5];
+        let cases = [
+            (vec![1, 3], "Revert positions must be strictly descending"),
+            (vec![3, 3], "Revert positions must be strictly descending"),
+            (vec![6, 2], "Invalid revert position 6 >= len 6"),
+        ];
+        for (positions, message) in cases {
+            let mut target = original.clone();
+            let error = catch_unwind(AssertUnwindSafe(|| {
+                revert(&mut target, RevertToken { positions })
+            }))
+            .unwrap_err();
+            assert_eq!(
+                error
+                    .downcast_ref::<String>()
+                    .map(String::as_str)
+                    .or_else(|| error.downcast_ref::<&str>().copied()),
+                Some(message)
+            );
+            assert_eq!(target, original);
+        }
+        // A token for a different target
+        let mut target = vec![1, 4, 5];
+        let token =
+            InsertionSet::from_iter([(0, 0), (2, 3), (3, 12)]).apply_transactional(&mut target);
+        let error =
+            catch_unwind(AssertUnwindSafe(|| revert(&mut vec![1, 4, 5], token))).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<String>().unwrap(),
+            "Invalid revert position 5 >= len 3"
+        );
+    }
+    #[test]
+    fn locations_in_window() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut full = insertions.list_updated_locations(&vector);
+        full.sort_by_key(|&(_, updated)| updated);
+        for start in 0..=full.len() {
+            for end in start..=full.len() + 1 {
+                let expected = full
+                    .iter()
+                    .cloned()
+                    .filter(|&(_, updated)| (start..end).contains(&updated))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    insertions.locations_in_window(vector.len(), start..end),
+                    expected
+                );
+            }
+        }
+        assert_eq!(
+            insertions.locations_in_window(vector.len(), 3..6),
+            vec![
+                (OriginalLocation::Insertion(2), 3),
+                (OriginalLocation::Original(1), 4),
+                (OriginalLocation::Original(2), 5),
+            ]
+        );
+    }
+    #[test]
+    fn check_growth_budget() {
+        let insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions.check_growth_budget(5, 9), Ok(()));
+        let err = insertions.check_growth_budget(5, 8).unwrap_err();
+        assert_eq!(err, GrowthExceeded { len: 9, max_len: 8 });
+        assert_eq!(err.excess(), 1);
+    }
+    #[test]
+    fn apply_within() {
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut target = vec![100, 101, 1, 4, 5, 7, 200, 201];
+        insertions.apply_within(&mut target, 2..6);
+        assert_eq!(target, vec![100, 101, 0, 1, 2, 3, 4, 5, 7, 9, 200, 201]);
+    }
+    #[test]
+    fn send_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        fn assert_send_sync<T: Send + Sync + 'static>() {
+            assert_send::<InsertionSet<T>>();
+            assert_sync::<InsertionSet<T>>();
+            assert_send::<Insertion<T>>();
+            assert_sync::<Insertion<T>>();
+            assert_send::<BulkShifter<'static, T>>();
+            assert_sync::<BulkShifter<'static, T>>();
+            assert_send::<DrainReverse<'static, T>>();
+            assert_send::<UpdatedLocations<'static, T>>();
+            assert_send::<InsertionDelta<T>>();
+        }
+        assert_send_sync::<u32>();
+        assert_send_sync::<String>();
+        // Only requires `T: Send`, just like `Vec<T>`
+        assert_send::<InsertionSet<std::cell::Cell<u32>>>();
+        assert_send::<RevertToken>();
+        assert_sync::<RevertToken>();
+    }
+    #[test]
+    fn edit_set() {
+        let mut edits = EditSet::new();
+        // Replaces the element at index 1
+        edits.delete(1);
+        edits.insert(1, 2);
+        edits.insert(0, 0);
+        edits.delete(3);
+        edits.delete(3);
+        edits.insert(4, 9);
+        assert_eq!(edits.applied(vec![1, 4, 5, 7, 11]), vec![0, 1, 2, 5, 9, 11]);
+    }
+    #[test]
+    fn edit_set_at_end() {
+        let mut edits = EditSet::new();
+        edits.delete(4);
+        edits.delete(3);
+        edits.insert(5, 12);
+        edits.insert(4, 10);
+        assert_eq!(edits.applied(vec![1, 4, 5, 7, 11]), vec![1, 4, 5, 10, 12]);
+        let mut edits = EditSet::<u32>::new();
+        edits.delete(0);
+        assert_eq!(edits.applied(vec![1]), Vec::<u32>::new());
+    }
+    #[test]
+    #[should_panic(expected = "Invalid insertion index 6 > len 5")]
+    fn edit_set_invalid_insertion() {
+        let mut edits = EditSet::new();
+        edits.delete(1);
+        edits.insert(6, 12);
+        edits.applied(vec![1, 4, 5, 7, 11]);
+    }
+    #[test]
+    fn edit_set_invalid_untouched() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut edits = EditSet::new();
+        edits.delete(1);
+        edits.insert(0, 0);
+        edits.insert(6, 12);
+        catch_unwind(AssertUnwindSafe(|| edits.apply(&mut target))).unwrap_err();
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+        // Anchored insertions are checked against the length after deleting
+        let mut insertions = InsertionSet::new();
+        insertions.insert_anchored(InsertionAnchor::FromEnd(5), 0);
+        let mut edits = EditSet::from(insertions);
+        edits.delete(4);
+        let error = catch_unwind(AssertUnwindSafe(|| edits.apply(&mut target))).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<String>().unwrap(),
+            "Invalid insertion index 5 > len 4"
+        );
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+    }
+    #[test]
+    fn try_apply() {
+        let mut insertions = [(0, 0), (9, 2), (1, 3), (7, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut target = Vec::with_capacity(6);
+        target.extend([1, 4, 5, 7, 11]);
+        let capacity = target.capacity();
+        assert_eq!(
+            insertions.try_apply(&mut target),
+            Err(InsertionError { index: 9, len: 5 })
+        );
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+        assert_eq!(target.capacity(), capacity);
+        let insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(
+            insertions.try_applied(target),
+            Ok(vec![0, 1, 2, 3, 4, 5, 7, 9, 11])
+        );
+    }
+    #[test]
+    fn apply_into_slice() {
+        use std::mem::MaybeUninit;
+        let build = |room: usize| {
+            let mut buf = [1, 4, 5, 7, 11]
+                .iter()
+                .map(|&value| MaybeUninit::new(value))
+                .collect::<Vec<_>>();
+            buf.resize_with(5 + room, MaybeUninit::uninit);
+            buf
+        };
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut short = build(3);
+        assert_eq!(
+            insertions.apply_into_slice(&mut short, 5),
+            Err(NeedsAllocation {
+                required: 4,
+                available: 3
+            })
+        );
+        let untouched = short[..5]
+            .iter()
+            .map(|value| unsafe { value.assume_init() })
+            .collect::<Vec<u32>>();
+        assert_eq!(untouched, vec![1, 4, 5, 7, 11]);
+        let mut exact = build(4);
+        assert_eq!(insertions.apply_into_slice(&mut exact, 5), Ok(9));
+        let result = exact
+            .iter()
+            .map(|value| unsafe { value.assume_init() })
+            .collect::<Vec<u32>>();
+        assert_eq!(result, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+
+        // Padding needs room too, and nothing is resolved unless it fits
+        let mut insertions = InsertionSet::new();
+        insertions.insert_anchored(InsertionAnchor::FromEnd(1), 9);
+        insertions.pad_to(9, 0);
+        let mut short = build(3);
+        assert_eq!(
+            insertions.apply_into_slice(&mut short, 5),
+            Err(NeedsAllocation {
+                required: 4,
+                available: 3
+            })
+        );
+        assert!(insertions.insertions().is_empty());
+        assert_eq!(
+            insertions.applied(vec![1, 2]),
+            vec![1, 9, 2, 0, 0, 0, 0, 0, 0]
+        );
+    }
+    #[test]
+    fn iter_applied() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (5, 12), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let iter = insertions.iter_applied(&vector);
+        assert_eq!(iter.len(), 10);
+        assert_eq!(
+            iter.cloned().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11, 12]
+        );
+
+        // Anchored insertions and padding stay queued
+        insertions.insert_anchored(InsertionAnchor::FromEnd(0), 13);
+        insertions.pad_to(12, 0);
+        let iter = insertions.iter_applied(&vector);
+        assert_eq!(iter.len(), 12);
+        assert_eq!(
+            iter.cloned().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11, 12, 13, 0]
+        );
+        assert_eq!(insertions.insertions().len(), 5);
+        assert_eq!(
+            insertions.applied(vec![1, 4, 5, 7, 11, 14]),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11, 12, 14, 13]
+        );
+    }
+    #[test]
+    fn clear() {
+        let mut insertions = InsertionSet::new();
+        insertions.reserve(16);
+        let capacity = insertions.capacity();
+        assert!(capacity >= 16);
+        for round in 0..3 {
+            insertions.insert(1, round);
+            insertions.insert_many_same(&[0, 2], 10);
+            insertions.pad_to(8, 20);
+            insertions.clear();
+            assert_eq!(insertions.desired_insertions(), 0);
+            assert_eq!(insertions.capacity(), capacity);
+            let mut target = vec![1, 2];
+            insertions.apply(&mut target);
+            assert_eq!(target, vec![1, 2]);
+        }
+    }
+    #[test]
+    fn clone_eq() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(1, 'a');
+        insertions.insert_many_same(&[1, 0], 'b');
+        insertions.insert(1, 'c');
+        let snapshot = insertions.clone();
+        assert_eq!(snapshot, insertions);
+        assert_eq!(
+            snapshot.applied(vec!['x', 'y']),
+            vec!['b', 'x', 'a', 'b', 'c', 'y']
+        );
+        let mut individual = InsertionSet::new();
+        individual.insert(1, 'a');
+        individual.insert(1, 'b');
+        individual.insert(0, 'b');
+        individual.insert(1, 'c');
+        assert_eq!(individual, insertions);
+
+        let mut reordered = InsertionSet::new();
+        reordered.insert(1, 'c');
+        reordered.insert(1, 'b');
+        reordered.insert(0, 'b');
+        reordered.insert(1, 'a');
+        assert_ne!(reordered, insertions);
+        assert_eq!(Insertion::new(1, 'a').clone(), Insertion::new(1, 'a'));
+    }
+    #[test]
+    fn merge() {
+        let mut first = [(0, 0), (1, 2), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let mut second = InsertionSet::new();
+        second.insert(1, 3);
+        second.insert_many_same(&[4], 10);
+        second.insert(0, 1);
+        first.merge(second);
+        first.extend(vec![Insertion::new(4, 11), Insertion::new(1, 4)]);
+        assert_eq!(
+            first.applied(vec![100, 101, 102, 103, 104]),
+            vec![0, 1, 100, 2, 3, 4, 101, 102, 103, 9, 10, 11, 104]
+        );
     }
-    shifter.finish();
-    assert_eq!(insertions.len(), 0, "Unexpected insertions");
-}
+    #[test]
+    fn debug() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(4, 9);
+        insertions.insert_many_same(&[1, 0], 2);
+        insertions.insert(1, 3);
+        assert_eq!(
+            format!("{:?}", insertions),
+            "InsertionSet { insertions: [(4, 9), (1, 2), (0, 2), (1, 3)], desired_insertions: 4 }"
+        );
+    }
+    #[test]
+    fn insert_anchored() {
+        let build = || {
+            let mut insertions = InsertionSet::new();
+            insertions.insert_anchored(InsertionAnchor::FromEnd(0), 12);
+            insertions.insert_anchored(InsertionAnchor::FromEnd(1), 10);
+            insertions.insert_anchored(InsertionAnchor::FromStart(0), 0);
+            insertions.insert(4, 9);
+            insertions
+        };
+        assert_eq!(
+            build().applied(vec![1, 4, 5, 7, 11]),
+            vec![0, 1, 4, 5, 7, 9, 10, 11, 12]
+        );
+        assert_eq!(
+            build().applied(vec![1, 4, 5, 7]),
+            vec![0, 1, 4, 5, 10, 7, 9, 12]
+        );
+        let mut insertions = build();
+        insertions.insert_anchored(InsertionAnchor::FromEnd(6), 100);
+        let mut target = vec![1, 4, 5, 7, 11];
+        assert_eq!(
+            insertions.try_apply(&mut target),
+            Err(InsertionError { index: 6, len: 5 })
+        );
+        assert_eq!(target, vec![1, 4, 5, 7, 11]);
+    }
+    #[test]
+    fn anchored_and_padding_everywhere() {
+        let build = || {
+            let mut insertions = InsertionSet::new();
+            insertions.insert(0, 9);
+            insertions.insert_anchored(InsertionAnchor::FromEnd(0), 7);
+            insertions.pad_to(5, 0);
+            insertions
+        };
+        let expected = vec![9, 1, 2, 7, 0];
+        let mut target = vec![1, 2];
+        let mut insertions = build();
+        assert_eq!(insertions.apply_with_counts(&mut target), (2, 3));
+        assert_eq!(target, expected);
+        assert!(insertions.is_empty());
+        let mut target = vec![1, 2];
+        build().apply_with_headroom(&mut target, 0);
+        assert_eq!(target, expected);
+        let mut target = vec![1, 2];
+        build().apply_limited(&mut target, 10);
+        assert_eq!(target, expected);
+        let mut target = vec![1, 2];
+        let positions = build()
+            .apply_yielding_changes(&mut target)
+            .collect::<Vec<_>>();
+        assert_eq!(target, expected);
+        assert_eq!(positions, vec![0, 3, 4]);
+        let mut target = vec![1, 2];
+        build().apply_with_scratch(&mut target, &mut Vec::new());
+        assert_eq!(target, expected);
+        let mut target = vec![0, 1, 2, 3];
+        build().apply_within(&mut target, 1..3);
+        assert_eq!(target, vec![0, 9, 1, 2, 7, 0, 3]);
+        let mut extracted = Vec::new();
+        let mut target = vec![1, 2];
+        build().apply_partitioning(&mut target, &mut extracted, |_| false);
+        assert_eq!(target, expected);
+        let delta = build().apply_recording_delta(&mut vec![1, 2]);
+        let mut target = vec![1, 2];
+        delta.replay(&mut target);
+        assert_eq!(target, expected);
 
-/// The original location of an element (before a set of insertions are applied)
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum OriginalLocation {
-    /// The element was a queued insertion with the specified index
-    Insertion(usize),
-    /// The element was originally part of the vector
-    Original(usize),
-}
+        let mut insertions = build();
+        assert_eq!(insertions.location_map_len(2), 5);
+        let locations = insertions.list_updated_locations(&[1, 2]);
+        assert_eq!(locations.len(), 5);
+        assert_eq!(locations[3], (OriginalLocation::Insertion(1), 3));
+        assert_eq!(insertions.final_index_of_original(1, &[1, 2]), 2);
+        assert_eq!(insertions.final_index_of_insertion(2, &[1, 2]), 4);
+        assert_eq!(insertions.max_inserted_position(2), Some(4));
+        assert_eq!(insertions.iter_updated_locations(2).len(), 5);
+        // Nothing was resolved permanently, so a longer target still works
+        assert_eq!(insertions.applied(vec![1, 2, 3, 4]), vec![9, 1, 2, 3, 4, 7]);
+    }
+    #[test]
+    fn sort_with() {
+        let build = || {
+            let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3), (5, 12), (1, 4)]
+                .iter()
+                .cloned()
+                .collect::<InsertionSet<u32>>();
+            insertions.insert_seq(0, 1, 1);
+            insertions.insert_seq(0, 0, 100);
+            insertions
+        };
+        let expected = vec![0, 100, 1, 1, 2, 3, 4, 4, 5, 7, 9, 11, 12];
+        let mut insertion_sorted = build();
+        insertion_sorted.sort_with(InsertionSortStrategy);
+        assert_eq!(insertion_sorted.applied(vec![1, 4, 5, 7, 11]), expected);
+        let mut std_sorted = build();
+        std_sorted.sort_with(StdStableSortStrategy);
+        assert_eq!(std_sorted.applied(vec![1, 4, 5, 7, 11]), expected);
+        // Both below and above the threshold
+        for threshold in [0, 4, 64] {
+            let mut hybrid_sorted = build();
+            hybrid_sorted.sort_with(HybridSortStrategy::new(threshold));
+            assert_eq!(hybrid_sorted.applied(vec![1, 4, 5, 7, 11]), expected);
+        }
+    }
+    #[test]
+    fn already_sorted() {
+        struct Unreachable;
+        impl SortStrategy for Unreachable {
+            fn sort_by_key<E, K, F>(&self, _elements: &mut [E], _key: F)
+            where
+                K: Ord,
+                F: FnMut(&E) -> K,
+            {
+                unreachable!("Already sorted")
+            }
+        }
+        let mut insertions = (0..10_000)
+            .map(|i| (i / 3, i))
+            .collect::<InsertionSet<usize>>();
+        insertions.sort_with(Unreachable);
+        insertions.insert_seq(10_000, 1, 10_000);
+        insertions.insert_seq(10_000, 2, 10_001);
+        insertions.sort_with(Unreachable);
+        let result = insertions.applied((0..10_000).collect());
+        assert_eq!(result.len(), 20_002);
+    }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let locations = insertions.list_updated_locations(&vector);
+        let queued = insertions
+            .clone()
+            .into_sorted_tuples()
+            .into_iter()
+            .map(Insertion::from)
+            .collect::<Vec<_>>();
+        let json = serde_json::to_string(&queued).unwrap();
+        assert!(json.starts_with(r#"[{"index":0,"element":0},"#));
+        let decoded = serde_json::from_str::<Vec<Insertion<u32>>>(&json).unwrap();
+        assert_eq!(decoded, queued);
+        let decoded = decoded.into_iter().collect::<InsertionSet<u32>>();
+        assert_eq!(decoded.applied(vector.clone()), insertions.applied(vector));
 
-/// Compute the updated locations of all elements (original + inserted).
-///
-/// See [InsertionSet::compute_updated_locations] for details
-pub fn compute_updated_locations<T, I, F>(target: &[T], mut insertions: I, mut updated: F)
-where
-    I: Iterator<Item = usize>,
-    I: ExactSizeIterator,
-    F: FnMut(OriginalLocation, usize),
-{
-    // This mirrors `apply_bulk_insertions` without actually shifting memory
-    let mut original_len = target.len();
-    let shifted_end = original_len + insertions.len();
-    let mut shifted_start = shifted_end;
-    let mut insertion_id = 0;
-    while original_len != shifted_start {
-        let insertion_index = insertions.next().expect("Expected more insertions!");
-        assert!(
-            insertion_index <= original_len,
-            "Invalid insertion index {} > len {}",
-            insertion_index,
-            original_len
+        let json = serde_json::to_string(&locations).unwrap();
+        assert!(json.contains(r#"[{"Original":0},1]"#));
+        assert_eq!(
+            serde_json::from_str::<Vec<(OriginalLocation, usize)>>(&json).unwrap(),
+            locations
         );
-        let moved_memory = original_len - insertion_index;
-        if moved_memory > 0 {
-            assert!(
-                shifted_start >= moved_memory && insertion_index <= shifted_start - moved_memory
-            );
-            update_range(
-                insertion_index..original_len,
-                shifted_start - moved_memory,
-                &mut updated,
-            );
-            shifted_start -= moved_memory;
-            original_len = insertion_index;
+    }
+    #[test]
+    fn apply_to_deque() {
+        let mut target = VecDeque::with_capacity(8);
+        target.extend([5, 7, 11]);
+        target.push_front(4);
+        target.push_front(1);
+        assert_eq!(target.as_slices(), (&[1, 4][..], &[5, 7, 11][..]));
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        insertions.apply_to_deque(&mut target);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+    #[test]
+    fn compute_original_locations() {
+        let vector = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let originals = insertions.compute_original_locations(&vector);
+        assert_eq!(
+            originals,
+            vec![
+                OriginalLocation::Insertion(0),
+                OriginalLocation::Original(0),
+                OriginalLocation::Insertion(1),
+                OriginalLocation::Insertion(2),
+                OriginalLocation::Original(1),
+                OriginalLocation::Original(2),
+                OriginalLocation::Original(3),
+                OriginalLocation::Insertion(3),
+                OriginalLocation::Original(4),
+            ]
+        );
+        let updated = insertions.list_updated_locations(&vector);
+        assert_eq!(updated.len(), originals.len());
+        for (original, updated) in updated {
+            assert_eq!(originals[updated], original);
         }
-        assert!(shifted_start > original_len);
-        shifted_start -= 1;
-        updated(OriginalLocation::Insertion(insertion_id), shifted_start);
-        insertion_id += 1;
     }
-    for original_index in 0..original_len {
-        updated(OriginalLocation::Original(original_index), original_index);
+    #[test]
+    fn inspect_insertions() {
+        let mut insertions = InsertionSet::new();
+        insertions.reserve(64);
+        insertions.insert(4, 9);
+        insertions.insert(1, 2);
+        insertions.insert(0, 0);
+        assert_eq!(
+            insertions.insertions(),
+            &[
+                Insertion::new(4, 9),
+                Insertion::new(1, 2),
+                Insertion::new(0, 0)
+            ]
+        );
+        insertions.shrink_to_fit();
+        assert!(insertions.capacity() < 64);
+        assert_eq!(
+            insertions.applied(vec![1, 4, 5, 7, 11]),
+            vec![0, 1, 2, 4, 5, 7, 9, 11]
+        );
     }
-    assert_eq!(insertions.len(), 0, "Unexpected insertions");
-}
-#[inline]
-fn update_range<F: FnMut(OriginalLocation, usize)>(
-    original: Range<usize>,
-    updated_start: usize,
-    func: &mut F,
-) {
-    let mut updated = updated_start;
-    for original_index in original {
-        func(OriginalLocation::Original(original_index), updated);
-        updated += 1;
+    #[test]
+    fn insert_all() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(2, 3);
+        insertions.insert_all(2, vec![4, 5, 6]);
+        insertions.insert(2, 7);
+        insertions.insert(0, 0);
+        assert_eq!(
+            insertions.applied(vec![1, 2, 8]),
+            vec![0, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
     }
-}
+    #[test]
+    fn bulk_deleter() {
+        use std::cell::Cell;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        struct Counted<'a> {
+            value: u32,
+            drops: &'a Cell<u32>,
+            panic_on_drop: bool,
+        }
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.drops.set(self.drops.get() + 1);
+                if self.panic_on_drop {
+                    panic!("Dropping {}", self.value);
+                }
+            }
+        }
+        let drops = Cell::new(0);
+        let build = |panicking: Option<u32>| {
+            (0..10)
+                .map(|value| Counted {
+                    value,
+                    drops: &drops,
+                    panic_on_drop: Some(value) == panicking,
+                })
+                .collect::<Vec<_>>()
+        };
+        let values = |target: &[Counted]| target.iter().map(|c| c.value).collect::<Vec<_>>();
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        let mut target = build(None);
+        let mut deleter = BulkDeleter::new(&mut target);
+        deleter.delete_range(2..4);
+        deleter.delete_range(4..4);
+        deleter.delete_range(7..8);
+        assert_eq!(drops.get(), 3);
+        assert_eq!(deleter.len(), 7);
+        deleter.finish();
+        assert_eq!(values(&target), vec![0, 1, 4, 5, 6, 8, 9]);
+        drop(target);
+        assert_eq!(drops.get(), 10);
+
+        // A panicking destructor must neither leak nor double drop
+        drops.set(0);
+        let mut target = build(Some(2));
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut deleter = BulkDeleter::new(&mut target);
+            deleter.delete_range(1..4);
+            deleter.delete_range(8..9);
+        }));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 3);
+        assert_eq!(values(&target), vec![0, 4, 5, 6, 7, 8, 9]);
+        drop(target);
+        assert_eq!(drops.get(), 10);
+
+        // Pushing into the gap, then making more room once it runs out
+        drops.set(0);
+        let pushed = |value| Counted {
+            value,
+            drops: &drops,
+            panic_on_drop: false,
+        };
+        let mut target = build(None);
+        let mut deleter = BulkDeleter::new(&mut target);
+        deleter.keep_next();
+        assert_eq!(deleter.room(), 0);
+        deleter.delete_next();
+        deleter.push(pushed(10));
+        assert_eq!(deleter.next_original().map(|c| c.value), Some(2));
+        deleter.make_room(2);
+        assert_eq!(deleter.room(), 2);
+        deleter.push(pushed(11));
+        deleter.push(pushed(12));
+        assert_eq!(values(deleter.kept()), vec![0, 10, 11, 12]);
+        let taken = deleter.take_next();
+        deleter.delete_range(5..7);
+        assert_eq!((deleter.deleted(), deleter.len()), (4, 9));
+        deleter.finish();
+        assert_eq!(taken.value, 2);
+        assert_eq!(drops.get(), 3);
+        assert_eq!(values(&target), vec![0, 10, 11, 12, 3, 4, 7, 8, 9]);
+        drop((taken, target));
+        assert_eq!(drops.get(), 13);
+
+        // A panicking destructor after making room must neither leak nor double drop
+        drops.set(0);
+        let mut target = build(Some(6));
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut deleter = BulkDeleter::new(&mut target);
+            deleter.make_room(3);
+            deleter.push(pushed(10));
+            deleter.keep_next();
+            deleter.delete_range(6..7);
+        }));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 1);
+        assert_eq!(values(&target), vec![10, 0, 1, 2, 3, 4, 5, 7, 8, 9]);
+        drop(target);
+        assert_eq!(drops.get(), 11);
+    }
     #[test]
-    fn basic() {
-        /*
-         * For example, given the vector `[1, 4, 5, 7, 11]`
-         * and the InsertionSet `[(0, 0), (1, 2), (1, 3) (4, 9)]`:
-         */
+    fn panic_safety() {
+        use std::cell::Cell;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        struct Counted<'a> {
+            value: u32,
+            drops: &'a Cell<u32>,
+        }
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.drops.set(self.drops.get() + 1);
+                if self.value == 100 {
+                    panic!("Dropping {}", self.value);
+                }
+            }
+        }
+        let drops = Cell::new(0);
+        let counted = |value| Counted {
+            value,
+            drops: &drops,
+        };
+        let values = |target: &[Counted]| target.iter().map(|c| c.value).collect::<Vec<_>>();
+        let build = |middle| {
+            let target = [1, 4, 5, 7, 11]
+                .into_iter()
+                .map(counted)
+                .collect::<Vec<_>>();
+            let queued = [(0, 0), (1, middle), (4, 9)]
+                .into_iter()
+                .map(|(index, value)| Insertion::new(index, counted(value)))
+                .collect::<Vec<_>>();
+            (target, queued)
+        };
+
+        // The iterator panics after the 9 has been placed (and the 11 shifted)
+        let (mut target, mut queued) = build(2);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let insertions = DrainReverse::new(&mut queued).inspect(|insertion| {
+                assert_ne!(insertion.element.value, 2, "Failed to produce insertion");
+            });
+            apply_bulk_insertions(&mut target, insertions)
+        }));
+        assert!(result.is_err());
+        assert_eq!(values(&target), vec![1, 4, 5, 7, 9, 11]);
+        assert_eq!(queued.len(), 1);
+        assert_eq!(drops.get(), 1);
+        drop((target, queued));
+        // Every element was dropped exactly once
+        assert_eq!(drops.get(), 8);
+
+        // A destructor panics at the same point
+        drops.set(0);
+        let (mut target, mut queued) = build(100);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let insertions = DrainReverse::new(&mut queued).map(|insertion| {
+                if insertion.element.value == 100 {
+                    drop(insertion);
+                    unreachable!();
+                }
+                insertion
+            });
+            apply_bulk_insertions(&mut target, insertions)
+        }));
+        assert!(result.is_err());
+        assert_eq!(values(&target), vec![1, 4, 5, 7, 9, 11]);
+        assert_eq!(drops.get(), 1);
+        drop((target, queued));
+        assert_eq!(drops.get(), 8);
+    }
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_sort() {
+        let build = || {
+            let mut insertions = (0..20_000)
+                .map(|i| ((i * 7919) % 1000, i))
+                .collect::<InsertionSet<usize>>();
+            insertions.insert_seq(500, 1, 20_000);
+            insertions
+        };
+        let target = (0..1000).collect::<Vec<usize>>();
+        let expected = build().applied(target.clone());
+        for threshold in [0, PARALLEL_SORT_THRESHOLD] {
+            let mut insertions = build();
+            insertions.par_sort(threshold);
+            assert_eq!(insertions.applied(target.clone()), expected);
+        }
+    }
+    #[test]
+    fn checked_insertion_set() {
+        let mut insertions = CheckedInsertionSet::new(5);
+        assert_eq!(insertions.try_insert(0, 0), Ok(()));
+        assert_eq!(insertions.try_insert(5, 12), Ok(()));
+        assert_eq!(
+            insertions.try_insert(6, 13),
+            Err(InsertionError { index: 6, len: 5 })
+        );
+        assert_eq!(insertions.try_push(Insertion::new(1, 2)), Ok(()));
+        assert_eq!(insertions.as_set().desired_insertions(), 3);
+        let mut target = vec![1, 4, 5, 7, 11];
+        insertions.apply(&mut target);
+        assert_eq!(target, vec![0, 1, 2, 4, 5, 7, 11, 12]);
+        // Reusing the set checks against the new length
+        assert_eq!(insertions.target_len(), 8);
+        assert_eq!(insertions.try_insert(8, 13), Ok(()));
+        assert_eq!(
+            insertions.try_insert(9, 14),
+            Err(InsertionError { index: 9, len: 8 })
+        );
+        assert_eq!(
+            insertions.applied(target),
+            vec![0, 1, 2, 4, 5, 7, 11, 12, 13]
+        );
+    }
+    #[test]
+    fn apply_reporting() {
+        let build = || {
+            [(0, 0), (1, 2), (1, 3), (4, 9)]
+                .iter()
+                .cloned()
+                .collect::<InsertionSet<u32>>()
+        };
+        let mut target = vec![1, 4, 5, 7, 11];
+        let stats = build().apply_reporting(&mut target);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        assert!(stats.reallocated);
+        assert_eq!(stats.old_capacity, 5);
+        assert!(stats.new_capacity >= 9);
+        // The 11 is moved once, then [4, 5, 7] and finally [1]
+        assert_eq!(stats.elements_moved, 5);
+
+        let mut target = Vec::with_capacity(9);
+        target.extend([1, 4, 5, 7, 11]);
+        let stats = build().apply_reporting(&mut target);
+        assert!(!stats.reallocated);
+        assert_eq!(stats.old_capacity, stats.new_capacity);
+    }
+    #[test]
+    fn into_iter() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(4, 9);
+        insertions.insert_many_same(&[1, 0], 2);
+        insertions.insert(1, 3);
+        assert_eq!(
+            (&insertions)
+                .into_iter()
+                .map(|i| (i.index, *i.element))
+                .collect::<Vec<_>>(),
+            vec![(4, 9), (1, 2), (0, 2), (1, 3)]
+        );
+        assert_eq!(insertions.iter_queued().len(), 4);
+        let incremented = insertions
+            .into_iter()
+            .filter(|insertion| insertion.index > 0)
+            .map(|insertion| Insertion::new(insertion.index, insertion.element + 1))
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(
+            incremented
+                .iter_queued()
+                .map(|insertion| Insertion::new(insertion.index, *insertion.element))
+                .collect::<Vec<_>>(),
+            vec![
+                Insertion::new(4, 10),
+                Insertion::new(1, 3),
+                Insertion::new(1, 4)
+            ]
+        );
+    }
+    #[test]
+    fn apply_tracking() {
+        let mut target = vec![1, 4, 5, 7, 11];
+        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let expected = insertions
+            .list_updated_locations(&target)
+            .into_iter()
+            .filter_map(|(original, updated)| match original {
+                OriginalLocation::Insertion(_) => Some(updated),
+                OriginalLocation::Original(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(insertions.apply_tracking(&mut target), expected);
+        assert_eq!(target, vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+
+        // Indexed by queued order, not sorted order
+        let mut insertions = InsertionSet::new();
+        insertions.insert(4, 'c');
+        insertions.insert_many_same(&[0, 1], 'a');
+        insertions.insert(0, 'b');
+        let mut target = vec!['w', 'x', 'y', 'z'];
+        assert_eq!(insertions.apply_tracking(&mut target), vec![7, 0, 3, 1]);
+        assert_eq!(target, vec!['a', 'b', 'w', 'a', 'x', 'y', 'z', 'c']);
+    }
+    #[test]
+    fn final_index_queries() {
         let vector = vec![1, 4, 5, 7, 11];
-        let insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3), (5, 12)]
             .iter()
             .cloned()
             .collect::<InsertionSet<u32>>();
-        assert_eq!(insertions.applied(vector), vec![0, 1, 2, 3, 4, 5, 7, 9, 11]);
+        for (original, updated) in insertions.list_updated_locations(&vector) {
+            let computed = match original {
+                OriginalLocation::Original(index) => {
+                    insertions.final_index_of_original(index, &vector)
+                }
+                OriginalLocation::Insertion(id) => insertions.final_index_of_insertion(id, &vector),
+            };
+            assert_eq!(computed, updated, "{:?}", original);
+        }
     }
     #[test]
-    fn updated_locations() {
-        /*
-         * For example, given the vector `[1, 4, 5, 7, 11]`
-         * and the InsertionSet `[(0, 0), (1, 2), (1, 3) (4, 9)]`:
-         */
+    #[should_panic(expected = "Invalid original index 5 >= len 5")]
+    fn final_index_of_original_out_of_range() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(2, 0);
+        insertions.final_index_of_original(5, &[1, 2, 3, 4, 5]);
+    }
+    #[test]
+    fn list_updated_locations_into() {
         let vector = vec![1, 4, 5, 7, 11];
-        let mut insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+        let mut insertions = [(4, 9), (1, 2), (0, 0), (1, 3)]
             .iter()
             .cloned()
             .collect::<InsertionSet<u32>>();
+        let mut buffer = vec![(OriginalLocation::Original(42), 42); 32];
+        let capacity = buffer.capacity();
+        insertions.list_updated_locations_into(&vector, &mut buffer);
+        assert_eq!(buffer, insertions.list_updated_locations(&vector));
+        assert!(buffer.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert_eq!(buffer.capacity(), capacity);
+        insertions.clear();
+        insertions.list_updated_locations_into(&vector[..2], &mut buffer);
         assert_eq!(
-            insertions.list_updated_locations(&vector),
+            buffer,
             vec![
-                (OriginalLocation::Insertion(0), 0),
-                (OriginalLocation::Original(0), 1),
-                (OriginalLocation::Insertion(1), 2),
-                (OriginalLocation::Insertion(2), 3),
-                (OriginalLocation::Original(1), 4),
-                (OriginalLocation::Original(2), 5),
-                (OriginalLocation::Original(3), 6),
-                (OriginalLocation::Insertion(3), 7),
-                (OriginalLocation::Original(4), 8),
+                (OriginalLocation::Original(0), 0),
+                (OriginalLocation::Original(1), 1)
+            ]
+        );
+    }
+    #[test]
+    fn with_capacity() {
+        let mut insertions = InsertionSet::with_capacity(100);
+        let capacity = insertions.capacity();
+        assert!(capacity >= 100);
+        for index in 0..100 {
+            insertions.insert(index, index);
+        }
+        assert_eq!(insertions.capacity(), capacity);
+        let collected = (0..50)
+            .map(|index| (index, index))
+            .collect::<InsertionSet<_>>();
+        assert!(collected.capacity() >= 50);
+        assert_eq!(collected.desired_insertions(), 50);
+    }
+    #[test]
+    fn push_sorted() {
+        let mut insertions = InsertionSet::new();
+        for &(index, element) in &[(0, 0), (1, 2), (1, 3), (4, 9)] {
+            insertions.push_sorted(Insertion::new(index, element));
+        }
+        assert!(insertions.sorted);
+        assert_eq!(
+            insertions.applied(vec![1, 4, 5, 7, 11]),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11]
+        );
+        // Queueing any other way falls back to sorting
+        let mut insertions = InsertionSet::new();
+        insertions.push_sorted(Insertion::new(3, 'c'));
+        insertions.insert(0, 'a');
+        assert!(!insertions.sorted);
+        insertions.sort();
+        assert!(insertions.sorted);
+        insertions.push_sorted(Insertion::new(3, 'd'));
+        assert!(insertions.sorted);
+        assert_eq!(
+            insertions.applied(vec!['w', 'x', 'y', 'z']),
+            vec!['a', 'w', 'x', 'y', 'c', 'd', 'z']
+        );
+    }
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Insertion index 1 is before the last index 4")]
+    fn push_sorted_out_of_order() {
+        let mut insertions = InsertionSet::new();
+        insertions.push_sorted(Insertion::new(4, 9));
+        insertions.push_sorted(Insertion::new(1, 2));
+    }
+    #[test]
+    fn is_empty() {
+        let mut insertions = InsertionSet::new();
+        assert!(insertions.is_empty());
+        let mut target = Vec::with_capacity(100);
+        target.extend(0..100);
+        insertions.apply(&mut target);
+        assert_eq!(target.capacity(), 100);
+        assert_eq!(target, (0..100).collect::<Vec<_>>());
+
+        insertions.pad_to(3, 0);
+        assert!(!insertions.is_empty());
+        insertions.clear();
+        insertions.insert_many_same(&[1], 7);
+        assert!(!insertions.is_empty());
+        assert_eq!(insertions.applied(vec![0, 1]), vec![0, 7, 1]);
+    }
+    #[test]
+    fn apply_cloned() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(2, 'c');
+        insertions.insert_seq(0, 2, 'b');
+        insertions.insert_seq(0, 1, 'a');
+        insertions.insert_anchored(InsertionAnchor::FromEnd(0), 'z');
+        let mut first = vec!['1', '2', '3'];
+        let mut second = vec!['x', 'y', 'w'];
+        insertions.apply_cloned(&mut first);
+        insertions.apply_cloned(&mut second);
+        assert_eq!(first, vec!['a', 'b', '1', '2', 'c', '3', 'z']);
+        assert_eq!(second, vec!['a', 'b', 'x', 'y', 'c', 'w', 'z']);
+        assert_eq!(insertions.desired_insertions(), 4);
+        assert_eq!(insertions.applied(vec!['1', '2', '3']), first);
+    }
+    #[test]
+    fn into_reverse_sorted() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(1, 'b');
+        insertions.insert_many_same(&[3, 0], 'x');
+        insertions.insert(1, 'c');
+        let reversed = insertions.into_reverse_sorted();
+        assert_eq!(reversed.len(), 4);
+        let reversed = reversed.collect::<Vec<_>>();
+        assert_eq!(
+            reversed,
+            vec![
+                Insertion::new(3, 'x'),
+                Insertion::new(1, 'c'),
+                Insertion::new(1, 'b'),
+                Insertion::new(0, 'x'),
+            ]
+        );
+        let mut target = vec!['1', '2', '3'];
+        apply_bulk_insertions(&mut target, reversed.into_iter());
+        assert_eq!(target, vec!['x', '1', 'b', 'c', '2', '3', 'x']);
+    }
+    #[test]
+    fn drain_applied() {
+        let insertions = [(0, 0), (1, 2), (1, 3), (4, 9)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        let drained = insertions.drain_applied(vec![1, 4, 5, 7, 11]);
+        assert_eq!(drained.len(), 9);
+        assert_eq!(
+            drained.collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11]
+        );
+
+        // Every element is dropped exactly once, even if the iterator is dropped early
+        use std::cell::Cell;
+        struct Counted<'a>(&'a Cell<u32>);
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+        let drops = Cell::new(0);
+        {
+            let mut insertions = InsertionSet::new();
+            insertions.insert(2, Counted(&drops));
+            insertions.insert(0, Counted(&drops));
+            insertions.insert(3, Counted(&drops));
+            let target = (0..3).map(|_| Counted(&drops)).collect::<Vec<_>>();
+            let mut drained = insertions.drain_applied(target);
+            drop(drained.next());
+            drop(drained.next());
+            assert_eq!(drops.get(), 2);
+        }
+        assert_eq!(drops.get(), 6);
+    }
+    #[test]
+    fn retain() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(4, 9);
+        insertions.insert_many_same(&[1, 3], 2);
+        insertions.insert_seq(1, 7, 3);
+        insertions.insert(0, 0);
+        insertions.insert_seq(1, 1, 5);
+        insertions.retain(|insertion| insertion.element != 2);
+        assert_eq!(insertions.desired_insertions(), 4);
+        let mut expected = InsertionSet::new();
+        expected.insert(4, 9);
+        expected.insert_seq(1, 7, 3);
+        expected.insert(0, 0);
+        expected.insert_seq(1, 1, 5);
+        assert_eq!(insertions, expected);
+        assert_eq!(
+            insertions.applied(vec![1, 4, 5, 7, 11]),
+            expected.applied(vec![1, 4, 5, 7, 11])
+        );
+    }
+    #[test]
+    fn insert_slice() {
+        let prologue = vec![String::from("push"), String::from("mov")];
+        let mut insertions = InsertionSet::new();
+        insertions.insert(1, String::from("a"));
+        insertions.insert_slice(1, &prologue);
+        insertions.insert_slice(0, &prologue);
+        assert_eq!(
+            insertions.applied(vec![String::from("x"), String::from("y")]),
+            vec!["push", "mov", "x", "a", "push", "mov", "y"]
+        );
+        assert_eq!(prologue, vec!["push", "mov"]);
+    }
+    #[test]
+    fn into_sorted_vec() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(1, 'b');
+        insertions.insert_many_same(&[3, 0], 'x');
+        insertions.insert(1, 'c');
+        insertions.insert(0, 'a');
+        assert_eq!(
+            insertions.into_sorted_vec(),
+            vec![
+                Insertion::new(0, 'x'),
+                Insertion::new(0, 'a'),
+                Insertion::new(1, 'b'),
+                Insertion::new(1, 'c'),
+                Insertion::new(3, 'x'),
             ]
         );
     }
     #[test]
+    #[cfg(feature = "smallvec")]
+    fn apply_to_smallvec() {
+        use smallvec::SmallVec;
+        let insertions = || {
+            [(0, 0), (1, 2), (1, 3), (4, 9)]
+                .iter()
+                .cloned()
+                .collect::<InsertionSet<u32>>()
+        };
+        let expected = [0, 1, 2, 3, 4, 5, 7, 9, 11];
+        let mut inline = SmallVec::<[u32; 16]>::from_slice(&[1, 4, 5, 7, 11]);
+        insertions().apply_to_smallvec(&mut inline);
+        assert!(!inline.spilled());
+        assert_eq!(inline.as_slice(), expected);
+        let mut spilled = SmallVec::<[u32; 8]>::from_slice(&[1, 4, 5, 7, 11]);
+        insertions().apply_to_smallvec(&mut spilled);
+        assert!(spilled.spilled());
+        assert_eq!(spilled.as_slice(), expected);
+    }
+    #[test]
+    fn apply_mapping() {
+        let mut insertions = [(0, "zero"), (1, "two"), (1, "three"), (4, "nine")]
+            .iter()
+            .map(|&(index, name)| (index, String::from(name)))
+            .collect::<InsertionSet<String>>();
+        insertions.insert(5, String::from("end"));
+        let mapped = insertions.apply_mapping(vec![1u32, 4, 5, 7, 11], |value| value.to_string());
+        assert_eq!(
+            mapped,
+            vec!["zero", "1", "two", "three", "4", "5", "7", "nine", "11", "end"]
+        );
+    }
+    #[test]
+    fn extend() {
+        let mut insertions = InsertionSet::new();
+        insertions.insert(1, 2);
+        insertions.insert_many_same(&[4], 9);
+        insertions.extend(vec![(1, 3), (0, 0)]);
+        insertions.extend(vec![Insertion::new(5, 12), Insertion::new(1, 4)]);
+        assert_eq!(insertions.desired_insertions(), 6);
+        assert!(insertions.capacity() >= 5);
+        let expected = [(1, 2), (4, 9), (1, 3), (0, 0), (5, 12), (1, 4)]
+            .iter()
+            .cloned()
+            .collect::<InsertionSet<u32>>();
+        assert_eq!(insertions, expected);
+        assert_eq!(
+            insertions.applied(vec![1, 4, 5, 7, 11]),
+            vec![0, 1, 2, 3, 4, 4, 5, 7, 9, 11, 12]
+        );
+    }
+    #[test]
+    fn lazy_insertion_set() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        let calls = Rc::new(Cell::new(0));
+        let build = || {
+            let mut insertions = LazyInsertionSet::new();
+            for &(index, value) in &[(4, 9), (1, 2), (0, 0), (1, 3)] {
+                let calls = calls.clone();
+                insertions.insert_with(index, move || {
+                    calls.set(calls.get() + 1);
+                    value
+                });
+            }
+            insertions
+        };
+        drop(build());
+        assert_eq!(calls.get(), 0);
+        assert_eq!(
+            build().applied(vec![1, 4, 5, 7, 11]),
+            vec![0, 1, 2, 3, 4, 5, 7, 9, 11]
+        );
+        assert_eq!(calls.get(), 4);
+
+        let mut boxed = LazyInsertionSet::<String>::new();
+        boxed.insert_boxed(1, || "b".repeat(2));
+        boxed.insert_boxed(0, String::new);
+        assert_eq!(boxed.desired_insertions(), 2);
+        assert_eq!(boxed.applied(vec!["a".into()]), vec!["", "a", "bb"]);
+    }
+    #[test]
     fn empty_updated_locations() {
         let vector = vec![1, 4, 5, 7, 11];
         assert_eq!(
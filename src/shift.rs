@@ -1,5 +1,9 @@
-use std::fmt::{self, Debug, Formatter};
-use std::{ptr, slice};
+#[cfg(feature = "allocator_api")]
+use alloc::alloc::{Allocator, Global};
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use core::ops::Range;
+use core::{mem, ptr, slice};
 
 /// A completely safe interface for shifting a vector's elements in bulk.
 ///
@@ -19,22 +23,30 @@ use std::{ptr, slice};
 /// ## Example
 /// 1. Assume we're given a 5-element vector of `[1, 4, 5, 7, 11]`,
 ///    and want to insert the value `1` at index `4`.
-///   1. Since we haven't created a [`BulkShift`] object yet,
+///   1. Since we haven't created a [`BulkShifter`] object yet,
 ///      all the elements occupy the normal (original) range of `(0, 5)`
-/// 2. First, we create a new [`BulkShift`] object with `desired_insertions = 1`,
+/// 2. First, we create a new [`BulkShifter`] object with `desired_insertions = 1`,
 ///    which will reserve space for 1 additional element, giving the memory `[1, 4, 5, 7, 11, undef]`
 ///   1. Original range: `[0, 5)` has 5 defined elements.
 ///   2. Middle range: `[5, 6)` has 1 _undefined_ element.
 ///   3. Shifted (final) range: `[6, 6)` has 0 _defined_ elements.
 /// 2. Move the element `11` from the original (left) side to the shifted (right) side
-///     giving the memory `[1, 4, 5, 7, undef, 11]`.
+///    giving the memory `[1, 4, 5, 7, undef, 11]`.
 ///  1. Original range: `[0, 5)` has 4 defined  elements (instead of 5).
 ///  2. Middle range: `[5, 6)` has 1 undefined element (but changed position)
 ///  2. Shifted range: `(5, 6)` has 1 defined element (instead of 0).
 /// 3. Insert the
-pub struct BulkShifter<'a, T: 'a> {
+///
+/// ## Panic safety
+/// If the shifter is dropped before it's finished (for example, during a panic),
+/// the shifted elements are moved back down to close the gap in the middle.
+/// This leaves a valid (if partially applied) vector,
+/// without leaking or double dropping any elements.
+///
+/// With the `allocator_api` feature, the target may use any allocator.
+pub struct BulkShifter<'a, T: 'a, #[cfg(feature = "allocator_api")] A: Allocator = Global> {
     /// The target vector we're working with
-    target: &'a mut Vec<T>,
+    target: &'a mut alloc_in!(Vec<T> in A),
     /// The inclusive start of the elements that have been shifted.
     /// For example, in [1, 2, undef, 3] the shifted_start is 3.
     ///
@@ -50,10 +62,39 @@ pub struct BulkShifter<'a, T: 'a> {
     /// room we've already reserved.
     shifted_end: usize,
 }
-impl<'a, T: 'a> BulkShifter<'a, T> {
-    pub fn new(target: &'a mut Vec<T>, desired_insertions: usize) -> Self {
-        target.reserve(desired_insertions);
-        let shifted_end = target.len() + desired_insertions;
+impl<'a, T: 'a, #[cfg(feature = "allocator_api")] A: Allocator> alloc_in!(BulkShifter<'a, T> in A) {
+    /// Create a shifter with room for the specified number of insertions
+    pub fn new(target: &'a mut alloc_in!(Vec<T> in A), desired_insertions: usize) -> Self {
+        Self::with_reserve_hint(target, desired_insertions, 0)
+    }
+    /// Create a shifter with room for the desired insertions,
+    /// reserving `headroom` additional elements of spare capacity for future use.
+    ///
+    /// The headroom never affects the length of the finished vector.
+    pub fn with_reserve_hint(
+        target: &'a mut alloc_in!(Vec<T> in A),
+        desired_insertions: usize,
+        headroom: usize,
+    ) -> Self {
+        /*
+         * A wrapped length would make everything below unsound (especially for zero-sized types,
+         * where the byte length is always valid), so this must never silently overflow.
+         */
+        let shifted_end = target
+            .len()
+            .checked_add(desired_insertions)
+            .expect("capacity overflow");
+        /*
+         * All our pointer offsets are within `[0, shifted_end)`,
+         * and `ptr::add` is undefined behavior if the offset overflows an `isize`.
+         * `Vec::reserve` should already guarantee this, but we don't want to rely on it.
+         */
+        assert_valid_byte_len::<T>(shifted_end);
+        target.reserve(
+            desired_insertions
+                .checked_add(headroom)
+                .expect("capacity overflow"),
+        );
         BulkShifter {
             target,
             shifted_end,
@@ -70,14 +111,16 @@ impl<'a, T: 'a> BulkShifter<'a, T> {
     /// Shifts all the values after the specified original `start`
     /// from the original values over to the shifted values.
     ///
-    /// Returns an [`InsufficientRoomError`] if there's not enough space to continue,
+    /// Returns the number of elements that were moved.
+    ///
+    /// Panics if there's not enough space to continue,
     /// since all these operations are done in place.
     #[inline]
-    pub fn shift_original(&mut self, start: usize) {
+    pub fn shift_original(&mut self, start: usize) -> usize {
         assert!(start <= self.len());
         let moved_memory = self.len() - start;
         if moved_memory == 0 {
-            return;
+            return 0;
         }
         /*
          * Since we need to allow overlapping copies,
@@ -100,6 +143,7 @@ impl<'a, T: 'a> BulkShifter<'a, T> {
             self.shifted_start -= moved_memory;
             self.target.set_len(start);
         }
+        moved_memory
     }
     /// Push the specified value to the start of the shifted elements
     #[inline]
@@ -110,18 +154,74 @@ impl<'a, T: 'a> BulkShifter<'a, T> {
             ptr::write(self.target.as_mut_ptr().add(self.shifted_start), value);
         }
     }
+    /// Undo all the shifting, dropping the elements that were pushed at the specified positions
+    /// and moving the original elements back where they started.
+    ///
+    /// The positions must be the indices of every pushed element in ascending order,
+    /// which leaves the target with exactly its original elements.
+    /// Panics if a position isn't after the previous one and within the shifted elements,
+    /// but even wrong positions never cause undefined behavior
+    /// (they only drop the wrong elements).
+    pub(crate) fn rollback<I: IntoIterator<Item = usize>>(mut self, pushed: I) {
+        for position in pushed {
+            assert!(
+                self.shifted_start <= position && position < self.shifted_end,
+                "Invalid pushed position {}",
+                position
+            );
+            self.unshift(position - self.shifted_start);
+            // Take it out first, so a panicking destructor leaves the shifter consistent
+            let pushed = unsafe { ptr::read(self.target.as_ptr().add(self.shifted_start)) };
+            self.shifted_start += 1;
+            drop(pushed);
+        }
+        self.unshift(self.shifted_len());
+    }
+    /// Move the first `count` shifted elements back to the end of the original elements
+    #[inline]
+    fn unshift(&mut self, count: usize) {
+        assert!(count <= self.shifted_len());
+        unsafe {
+            let ptr = self.target.as_mut_ptr();
+            ptr::copy(ptr.add(self.shifted_start), ptr.add(self.len()), count);
+            self.shifted_start += count;
+            self.target.set_len(self.len() + count);
+        }
+    }
     /// The length of the valid original elements.
     #[inline]
     pub fn len(&self) -> usize {
         self.target.len()
     }
+    /// Check if there are no valid original elements left
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.target.is_empty()
+    }
+    /// Finish shifting, returning the target vector with all of its elements.
+    ///
+    /// Panics if there is still uninitialized memory in the middle.
+    #[inline]
+    pub fn finish(self) -> &'a mut alloc_in!(Vec<T> in A) {
+        match self.try_finish() {
+            Ok(target) => target,
+            Err(_) => panic!("Unfinished"),
+        }
+    }
+    /// Finish shifting if there is no uninitialized memory left in the middle,
+    /// otherwise give back the unfinished shifter so it can be inspected or continued.
     #[inline]
-    pub fn finish(self) -> &'a mut Vec<T> {
-        assert!(self.is_finished(), "Unfinished");
+    pub fn try_finish(self) -> Result<&'a mut alloc_in!(Vec<T> in A), Self> {
+        if !self.is_finished() {
+            return Err(self);
+        }
+        // We're taking over the target, so we don't want the unfinished cleanup
+        let this = mem::ManuallyDrop::new(self);
         unsafe {
-            self.target.set_len(self.shifted_end);
+            let target = ptr::read(&this.target);
+            target.set_len(this.shifted_end);
+            Ok(target)
         }
-        self.target
     }
     /// Slice the elements that have been shifted to the right
     #[inline]
@@ -133,13 +233,43 @@ impl<'a, T: 'a> BulkShifter<'a, T> {
             )
         }
     }
+    /// The inclusive start index of the elements that have been shifted
+    #[inline]
+    pub fn shifted_start(&self) -> usize {
+        self.shifted_start
+    }
     /// The number of elements that have been shifted to the right
     #[inline]
     pub fn shifted_len(&self) -> usize {
         self.shifted_end - self.shifted_start
     }
 }
-impl<'a, T: Debug + 'a> Debug for BulkShifter<'a, T> {
+/// Check that `len` elements of type `T` fit within `isize::MAX` bytes,
+/// panicking with a "capacity overflow" (just like `Vec`) otherwise.
+#[inline]
+pub(crate) fn assert_valid_byte_len<T>(len: usize) {
+    let valid = match len.checked_mul(mem::size_of::<T>()) {
+        Some(bytes) => bytes <= isize::MAX as usize,
+        None => false,
+    };
+    assert!(valid, "capacity overflow");
+}
+impl<'a, T: 'a, #[cfg(feature = "allocator_api")] A: Allocator> Drop for alloc_in!(BulkShifter<'a, T> in A) {
+    fn drop(&mut self) {
+        // Close the gap in the middle, keeping everything that was already shifted
+        let shifted_len = self.shifted_len();
+        unsafe {
+            let ptr = self.target.as_mut_ptr();
+            ptr::copy(
+                ptr.add(self.shifted_start),
+                ptr.add(self.len()),
+                shifted_len,
+            );
+            self.target.set_len(self.len() + shifted_len);
+        }
+    }
+}
+impl<'a, T: Debug + 'a, #[cfg(feature = "allocator_api")] A: Allocator> Debug for alloc_in!(BulkShifter<'a, T> in A) {
     #[inline]
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("BulkShifter")
@@ -149,3 +279,245 @@ impl<'a, T: Debug + 'a> Debug for BulkShifter<'a, T> {
             .finish()
     }
 }
+
+/// A completely safe interface for deleting ranges of a vector's elements in bulk.
+///
+/// This is the mirror image of [`BulkShifter`]:
+/// ranges are deleted in ascending order, and the elements between them are shifted left
+/// to close the gaps, so every element is moved at most once.
+/// New elements can also be pushed into the gap as it goes,
+/// which allows fusing deletions and insertions into a single forward walk.
+///
+/// While work is in progress, the memory is split into three ranges:
+/// the kept (or pushed) elements `[0, kept)` that are already in their final position,
+/// the gap `[kept, read_at)` of deleted (or moved) memory,
+/// and the remaining original elements starting at `read_at`.
+/// The length of the vector is only `kept`, so a panic while dropping a deleted element
+/// can't cause a double drop.
+/// The remaining elements are shifted down once the deleter is dropped (even during a panic),
+/// so nothing is ever leaked.
+pub struct BulkDeleter<'a, T: 'a> {
+    /// The target vector we're working with
+    target: &'a mut Vec<T>,
+    /// The exclusive end of the elements that are in their final position
+    kept: usize,
+    /// The original index of the first original element that hasn't been processed yet
+    read: usize,
+    /// The current position of the first unprocessed element,
+    /// which is after its original index if room was made for pushing.
+    read_at: usize,
+    /// The length of the vector before any deletions
+    original_len: usize,
+    /// The number of new elements pushed so far
+    pushed: usize,
+}
+impl<'a, T: 'a> BulkDeleter<'a, T> {
+    /// Create a deleter for the specified vector
+    #[inline]
+    pub fn new(target: &'a mut Vec<T>) -> Self {
+        let original_len = target.len();
+        unsafe {
+            target.set_len(0);
+        }
+        BulkDeleter {
+            target,
+            kept: 0,
+            read: 0,
+            read_at: 0,
+            original_len,
+            pushed: 0,
+        }
+    }
+    /// Delete (and drop) the elements in the specified range of original indices.
+    ///
+    /// Ranges must be deleted in ascending order, without overlapping.
+    /// Panics if the range starts before the end of the previous one,
+    /// or is out of bounds of the original vector.
+    pub fn delete_range(&mut self, range: Range<usize>) {
+        let start = self.skip_range(range.clone());
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                self.target.as_mut_ptr().add(start),
+                range.len(),
+            ));
+        }
+    }
+    /// Delete the elements in the specified range of original indices like [Self::delete_range],
+    /// but move them onto the end of `removed` instead of dropping them.
+    pub fn take_range(&mut self, range: Range<usize>, removed: &mut Vec<T>) {
+        // Reserve first, so nothing can panic while the elements are in limbo
+        removed.reserve(range.len());
+        let start = self.skip_range(range.clone());
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.target.as_ptr().add(start),
+                removed.as_mut_ptr().add(removed.len()),
+                range.len(),
+            );
+            removed.set_len(removed.len() + range.len());
+        }
+    }
+    /// Move the kept elements before the range into their final position,
+    /// then skip past the range, leaving its elements to be dropped (or moved) by the caller.
+    ///
+    /// Returns the current position of the skipped elements.
+    fn skip_range(&mut self, range: Range<usize>) -> usize {
+        assert!(
+            self.read <= range.start && range.start <= range.end && range.end <= self.original_len,
+            "Invalid deletion range {:?} (after {}) for len {}",
+            range,
+            self.read,
+            self.original_len
+        );
+        let kept_count = range.start - self.read;
+        let start = self.read_at + kept_count;
+        unsafe {
+            // Nothing needs to move until the first deletion (or push)
+            if self.kept != self.read_at {
+                ptr::copy(
+                    self.target.as_ptr().add(self.read_at),
+                    self.target.as_mut_ptr().add(self.kept),
+                    kept_count,
+                );
+            }
+            self.kept += kept_count;
+            // Advance first, so a panicking destructor can't cause a double drop
+            self.read = range.end;
+            self.read_at = start + range.len();
+            self.target.set_len(self.kept);
+        }
+        start
+    }
+    /// The next original element that hasn't been kept or deleted yet,
+    /// or `None` if they have all been processed.
+    #[inline]
+    pub fn next_original(&self) -> Option<&T> {
+        if self.read < self.original_len {
+            Some(unsafe { &*self.target.as_ptr().add(self.read_at) })
+        } else {
+            None
+        }
+    }
+    /// Keep the next original element, moving it into its final position.
+    ///
+    /// Panics if there are no remaining original elements.
+    #[inline]
+    pub fn keep_next(&mut self) {
+        self.skip_range(self.read + 1..self.read + 1);
+    }
+    /// Delete (and drop) the next original element.
+    ///
+    /// Panics if there are no remaining original elements.
+    #[inline]
+    pub fn delete_next(&mut self) {
+        self.delete_range(self.read..self.read + 1);
+    }
+    /// Delete the next original element, moving it out instead of dropping it.
+    ///
+    /// Panics if there are no remaining original elements.
+    #[inline]
+    pub fn take_next(&mut self) -> T {
+        let start = self.skip_range(self.read..self.read + 1);
+        unsafe { ptr::read(self.target.as_ptr().add(start)) }
+    }
+    /// The elements that are already in their final position
+    #[inline]
+    pub fn kept(&self) -> &[T] {
+        &self.target[..]
+    }
+    /// The number of elements that can be pushed without making more room
+    #[inline]
+    pub fn room(&self) -> usize {
+        self.read_at - self.kept
+    }
+    /// Push a new element after the kept elements, filling part of the gap.
+    ///
+    /// Panics if there's no room left (see [Self::make_room]).
+    #[inline]
+    pub fn push(&mut self, element: T) {
+        assert!(self.room() > 0, "No room to push");
+        unsafe {
+            ptr::write(self.target.as_mut_ptr().add(self.kept), element);
+            self.kept += 1;
+            self.target.set_len(self.kept);
+        }
+        self.pushed += 1;
+    }
+    /// Ensure there's room to push at least `additional` elements,
+    /// growing the vector and shifting the remaining original elements right if needed.
+    ///
+    /// This moves all the remaining elements (twice), so callers should make room for
+    /// everything they still need to push at once, and only when they run out.
+    pub fn make_room(&mut self, additional: usize) {
+        let room = self.room();
+        if room >= additional {
+            return;
+        }
+        let remaining = self.original_len - self.read;
+        unsafe {
+            /*
+             * Close the gap first, since the vector only preserves its initialized elements when it
+             * reallocates. The deleter is consistent in the meantime, in case reserving panics.
+             */
+            ptr::copy(
+                self.target.as_ptr().add(self.read_at),
+                self.target.as_mut_ptr().add(self.kept),
+                remaining,
+            );
+            self.read_at = self.kept;
+            self.target.set_len(self.kept + remaining);
+            self.target.reserve(additional);
+            ptr::copy(
+                self.target.as_ptr().add(self.kept),
+                self.target.as_mut_ptr().add(self.kept + additional),
+                remaining,
+            );
+            self.read_at = self.kept + additional;
+            self.target.set_len(self.kept);
+        }
+    }
+    /// The number of elements that have been deleted so far
+    #[inline]
+    pub fn deleted(&self) -> usize {
+        self.read + self.pushed - self.kept
+    }
+    /// The length the vector will have once the deleter is finished
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.kept + (self.original_len - self.read)
+    }
+    /// Check if the vector will be empty once the deleter is finished
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Finish deleting, shifting down the remaining elements.
+    ///
+    /// This is exactly the same as dropping the deleter.
+    #[inline]
+    pub fn finish(self) {}
+}
+impl<'a, T: 'a> Drop for BulkDeleter<'a, T> {
+    fn drop(&mut self) {
+        let remaining = self.original_len - self.read;
+        unsafe {
+            ptr::copy(
+                self.target.as_ptr().add(self.read_at),
+                self.target.as_mut_ptr().add(self.kept),
+                remaining,
+            );
+            self.target.set_len(self.kept + remaining);
+        }
+    }
+}
+impl<'a, T: Debug + 'a> Debug for BulkDeleter<'a, T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("BulkDeleter")
+            .field("kept", &self.target)
+            .field("read", &self.read)
+            .field("read_at", &self.read_at)
+            .field("original_len", &self.original_len)
+            .finish()
+    }
+}
@@ -0,0 +1,96 @@
+//! Pluggable strategies for sorting the queued insertions.
+//!
+//! The right choice depends on the distribution of the input,
+//! so [`InsertionSet::sort_with`](crate::InsertionSet::sort_with) lets the user pick one.
+
+/// A stable sorting algorithm for the queued insertions.
+///
+/// Implementations must be stable (never reordering elements with equal keys),
+/// since insertions at the same index are applied in the order they were queued.
+pub trait SortStrategy {
+    /// Stably sort the elements by the specified key
+    fn sort_by_key<E, K, F>(&self, elements: &mut [E], key: F)
+    where
+        K: Ord,
+        F: FnMut(&E) -> K;
+}
+
+/// Sorts using insertion sort, which never allocates.
+///
+/// This has average running time `O(nk)`, where `k` is the average distance
+/// of each element from its proper position.
+/// That's great for input that is already mostly sorted,
+/// but quadratic in the worst case.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InsertionSortStrategy;
+impl SortStrategy for InsertionSortStrategy {
+    fn sort_by_key<E, K, F>(&self, elements: &mut [E], mut key: F)
+    where
+        K: Ord,
+        F: FnMut(&E) -> K,
+    {
+        for sorted in 1..elements.len() {
+            let mut index = sorted;
+            while index > 0 && key(&elements[index - 1]) > key(&elements[index]) {
+                elements.swap(index - 1, index);
+                index -= 1;
+            }
+        }
+    }
+}
+
+/// Sorts using [`slice::sort_by_key`], which is `O(n * log(n))` in the worst case
+/// but may allocate.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdStableSortStrategy;
+impl SortStrategy for StdStableSortStrategy {
+    #[inline]
+    fn sort_by_key<E, K, F>(&self, elements: &mut [E], key: F)
+    where
+        K: Ord,
+        F: FnMut(&E) -> K,
+    {
+        elements.sort_by_key(key);
+    }
+}
+
+/// Sorts small inputs with [`InsertionSortStrategy`], which never allocates,
+/// but falls back to [`StdStableSortStrategy`] for anything longer than the threshold.
+///
+/// This bounds the quadratic worst case of insertion sort to inputs that are cheap anyway.
+/// With the default threshold, this is the default strategy used by [`InsertionSet::apply`](crate::InsertionSet::apply).
+#[derive(Copy, Clone, Debug)]
+pub struct HybridSortStrategy {
+    /// The maximum length that is sorted with insertion sort
+    pub threshold: usize,
+}
+impl HybridSortStrategy {
+    /// The threshold used by default, which is small enough that
+    /// insertion sort is never much slower than the stdlib sort
+    pub const DEFAULT_THRESHOLD: usize = 64;
+    /// Create a strategy using insertion sort for at most `threshold` elements
+    #[inline]
+    pub const fn new(threshold: usize) -> Self {
+        HybridSortStrategy { threshold }
+    }
+}
+impl Default for HybridSortStrategy {
+    #[inline]
+    fn default() -> Self {
+        HybridSortStrategy::new(Self::DEFAULT_THRESHOLD)
+    }
+}
+impl SortStrategy for HybridSortStrategy {
+    #[inline]
+    fn sort_by_key<E, K, F>(&self, elements: &mut [E], key: F)
+    where
+        K: Ord,
+        F: FnMut(&E) -> K,
+    {
+        if elements.len() <= self.threshold {
+            InsertionSortStrategy.sort_by_key(elements, key)
+        } else {
+            StdStableSortStrategy.sort_by_key(elements, key)
+        }
+    }
+}
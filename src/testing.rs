@@ -0,0 +1,81 @@
+//! Helpers for testing code that relies on the stable ordering of insertions.
+//!
+//! Multiple insertions at the same index are guaranteed to be applied in the order they were queued.
+//! Wrapping elements in [`Tagged`] records that order,
+//! so the result of an apply can be checked with [`assert_stable`].
+//!
+//! This module is only available with the `testing` feature.
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use crate::InsertionSet;
+
+/// An element tagged with the sequence number it was queued with.
+///
+/// Elements that were originally part of the vector have no sequence number.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Tagged<T> {
+    /// The order this element was queued for insertion,
+    /// or `None` if it was an original element.
+    pub seq: Option<usize>,
+    /// The wrapped value
+    pub value: T,
+}
+impl<T> Tagged<T> {
+    /// Tag an element that was originally part of the vector
+    #[inline]
+    pub fn original(value: T) -> Self {
+        Tagged { seq: None, value }
+    }
+    /// Tag an element that was queued for insertion with the specified sequence number
+    #[inline]
+    pub fn inserted(seq: usize, value: T) -> Self {
+        Tagged {
+            seq: Some(seq),
+            value,
+        }
+    }
+}
+
+/// Tag all the elements of the original vector
+pub fn tag_originals<T>(values: Vec<T>) -> Vec<Tagged<T>> {
+    values.into_iter().map(Tagged::original).collect()
+}
+
+/// Queue the value for insertion, tagging it with the next sequence number.
+///
+/// Sequence numbers are assigned in push order.
+#[inline]
+pub fn insert_tagged<T>(set: &mut InsertionSet<Tagged<T>>, index: usize, value: T) {
+    let seq = set.desired_insertions();
+    set.insert(index, Tagged::inserted(seq, value));
+}
+
+/// Find the first inserted element that was applied out of push order,
+/// returning its index in the result.
+///
+/// Insertions at distinct indices are always separated by original elements,
+/// so each contiguous run of inserted elements must have increasing sequence numbers.
+pub fn find_unstable<T>(result: &[Tagged<T>]) -> Option<usize> {
+    let mut last_seq = None;
+    for (index, element) in result.iter().enumerate() {
+        match (last_seq, element.seq) {
+            (Some(last), Some(seq)) if seq <= last => return Some(index),
+            _ => last_seq = element.seq,
+        }
+    }
+    None
+}
+
+/// Assert that the applied result preserved push order for insertions at equal indices.
+#[track_caller]
+pub fn assert_stable<T: Debug>(result: &[Tagged<T>]) {
+    if let Some(index) = find_unstable(result) {
+        panic!(
+            "Unstable insertion at index {}: {:?} after {:?}",
+            index,
+            result[index],
+            result[index - 1]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Checks that sets and vectors with a custom allocator never touch the global allocator.
+//!
+//! This needs its own test binary, since it counts every global allocation.
+#![feature(allocator_api)]
+use std::alloc::{AllocError, Allocator, GlobalAlloc, Layout, System};
+use std::cell::{Cell, UnsafeCell};
+use std::ptr::NonNull;
+
+use insertion_set::InsertionSet;
+
+struct CountingAllocator;
+thread_local! {
+    /// Counted per thread, so tests running in parallel don't interfere
+    static GLOBAL_ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        GLOBAL_ALLOCATIONS.set(GLOBAL_ALLOCATIONS.get() + 1);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// A bump allocator over a fixed buffer, which never frees anything
+struct Bump {
+    memory: UnsafeCell<[u64; 128]>,
+    used: Cell<usize>,
+    allocations: Cell<usize>,
+}
+unsafe impl Allocator for &Bump {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        assert!(layout.align() <= 8);
+        let start = self.used.get().next_multiple_of(8);
+        let end = start + layout.size();
+        if end > 128 * 8 {
+            return Err(AllocError);
+        }
+        self.used.set(end);
+        self.allocations.set(self.allocations.get() + 1);
+        let ptr = unsafe { self.memory.get().cast::<u8>().add(start) };
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            layout.size(),
+        ))
+    }
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+impl Bump {
+    fn new() -> Self {
+        Bump {
+            memory: UnsafeCell::new([0; 128]),
+            used: Cell::new(0),
+            allocations: Cell::new(0),
+        }
+    }
+}
+
+#[test]
+fn apply_in_bump() {
+    let bump = Bump::new();
+    let before = GLOBAL_ALLOCATIONS.get();
+    let mut target = Vec::with_capacity_in(5, &bump);
+    target.extend([1u32, 4, 5, 7, 11]);
+    let mut insertions = InsertionSet::with_capacity_in(4, &bump);
+    insertions.insert(4, 9);
+    insertions.insert(0, 0);
+    insertions.insert(1, 2);
+    insertions.insert(1, 3);
+    insertions.apply(&mut target);
+    assert_eq!(GLOBAL_ALLOCATIONS.get(), before);
+    // The target, the insertions, and then the target again to make room
+    assert_eq!(bump.allocations.get(), 3);
+    assert!(insertions.is_empty());
+    assert!(std::ptr::eq(*insertions.allocator(), &bump));
+    assert_eq!(target, [0, 1, 2, 3, 4, 5, 7, 9, 11]);
+}
+
+#[test]
+fn applied_keeps_allocator() {
+    let bump = Bump::new();
+    let mut target = Vec::new_in(&bump);
+    target.extend([1u32, 4, 5]);
+    let mut insertions = InsertionSet::new_in(&bump);
+    insertions.insert(3, 12);
+    let target: Vec<u32, &Bump> = insertions.applied(target);
+    assert!(std::ptr::eq(*target.allocator(), &bump));
+    assert_eq!(target, [1, 4, 5, 12]);
+    // Sets with the global allocator can still be applied to vectors with a custom one
+    let target = InsertionSet::from_iter([(0, 0)]).applied(target);
+    assert_eq!(target, [0, 1, 4, 5, 12]);
+}
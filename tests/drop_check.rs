@@ -0,0 +1,111 @@
+//! Checks that sets dropped without being applied are caught in debug builds.
+#![cfg(debug_assertions)]
+use std::panic::catch_unwind;
+
+use insertion_set::{InsertionAnchor, InsertionSet, LazyInsertionSet};
+
+fn queued() -> InsertionSet<u32> {
+    [(0, 0), (1, 2), (1, 3), (4, 9)].iter().cloned().collect()
+}
+
+#[test]
+#[should_panic(expected = "InsertionSet with 4 insertions was dropped without being applied")]
+fn dropped_without_applying() {
+    drop(queued());
+}
+
+#[test]
+fn applied_or_discarded() {
+    let mut target = vec![1, 4, 5, 7, 11];
+    let mut insertions = queued();
+    insertions.apply(&mut target);
+    drop(insertions);
+    assert_eq!(queued().applied(vec![1, 4, 5, 7, 11]), target);
+    queued().discard();
+    // Empty sets are fine to throw away
+    drop(InsertionSet::<u32>::new());
+    let mut insertions = queued();
+    insertions.clear();
+    drop(insertions);
+}
+
+#[test]
+fn no_double_panic() {
+    let result = catch_unwind(|| {
+        let _insertions = queued();
+        panic!("Unrelated");
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+#[should_panic(expected = "InsertionSet with 1 insertions and padding to len 8 was dropped")]
+fn dropped_with_padding() {
+    let mut insertions = InsertionSet::new();
+    insertions.insert(0, 0);
+    insertions.pad_to(8, 0);
+    drop(insertions);
+}
+
+#[test]
+#[should_panic(expected = "InsertionSet with 1 insertions was dropped")]
+fn dropped_with_anchor() {
+    let mut insertions = InsertionSet::new();
+    insertions.insert_anchored(InsertionAnchor::FromEnd(0), 0);
+    drop(insertions);
+}
+
+fn pending() -> InsertionSet<u32> {
+    let mut insertions = queued();
+    insertions.insert_anchored(InsertionAnchor::FromEnd(0), 12);
+    insertions.pad_to(12, 0);
+    insertions
+}
+
+#[test]
+fn consumed_without_a_target() {
+    assert_eq!(pending().into_iter().count(), 4);
+    assert_eq!(pending().into_sorted_vec().len(), 4);
+    assert_eq!(pending().into_sorted_tuples().len(), 4);
+    assert_eq!(pending().into_reverse_sorted().len(), 4);
+}
+
+#[test]
+fn consumed_with_a_target() {
+    let expected = vec![0, 1, 2, 3, 4, 5, 7, 9, 11, 12, 0, 0];
+    let original = || vec![1, 4, 5, 7, 11];
+    let mut target = original();
+    pending().apply_with_counts(&mut target);
+    assert_eq!(target, expected);
+    assert_eq!(
+        pending().applied_converting(vec![1u64, 4, 5, 7, 11]).len(),
+        12
+    );
+    assert_eq!(pending().apply_mapping(original(), |x| x), expected);
+    assert_eq!(
+        pending().drain_applied(original()).collect::<Vec<_>>(),
+        expected
+    );
+    let mut target = original();
+    let (shifter, _) = pending().into_shifter(&mut target);
+    drop(shifter);
+    let mut target = original();
+    assert_eq!(pending().apply_yielding_changes(&mut target).count(), 7);
+    let mut target = original();
+    pending().apply_transactional(&mut target);
+    assert_eq!(target, expected);
+    let mut target = original();
+    pending().apply_recording_delta(&mut target);
+    assert_eq!(target, expected);
+    let mut first = original();
+    let mut second = original();
+    pending().apply_to_many([&mut first, &mut second]);
+    assert_eq!((first, second), (expected.clone(), expected));
+}
+
+#[test]
+fn lazy_dropped_without_applying() {
+    let mut insertions = LazyInsertionSet::<u32>::new();
+    insertions.insert_boxed(0, || unreachable!());
+    drop(insertions);
+}